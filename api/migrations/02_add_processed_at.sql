@@ -0,0 +1 @@
+alter table payments.log add column if not exists processed_at timestamptz;
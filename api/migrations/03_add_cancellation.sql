@@ -0,0 +1,4 @@
+alter table payments.log add column if not exists cancelled_at timestamptz;
+-- 'user' or 'system'
+alter table payments.log add column if not exists cancelled_by text;
+alter table payments.log add column if not exists cancel_reason text;
@@ -0,0 +1,11 @@
+create table if not exists payments.processor_attempts (
+    id bigserial primary key,
+    payment_id uuid not null,
+    processor text not null,
+    attempt int not null,
+    succeeded boolean not null,
+    error text,
+    attempted_at timestamptz not null default now()
+);
+
+create index if not exists processor_attempts_payment_id_idx on payments.processor_attempts (payment_id);
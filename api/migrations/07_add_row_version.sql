@@ -0,0 +1,12 @@
+alter table payments.log add column if not exists row_version bigint not null default 0;
+
+create or replace function payments.bump_row_version() returns trigger as $$
+begin
+    new.row_version := old.row_version + 1;
+    return new;
+end;
+$$ language plpgsql;
+
+drop trigger if exists log_bump_row_version on payments.log;
+create trigger log_bump_row_version before update on payments.log
+    for each row execute function payments.bump_row_version();
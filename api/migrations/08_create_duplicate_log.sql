@@ -0,0 +1,8 @@
+create table if not exists payments.duplicate_log (
+    id bigserial primary key,
+    correlation_id uuid not null,
+    received_at timestamptz not null,
+    source_ip inet
+);
+
+create index if not exists duplicate_log_received_at_idx on payments.duplicate_log (received_at);
@@ -0,0 +1,10 @@
+create table if not exists payments.dead_letter (
+    id uuid primary key,
+    amount decimal not null,
+    requested_at timestamptz not null,
+    failed_at timestamptz not null default now(),
+    last_error text not null,
+    attempts int not null
+);
+
+create index if not exists dead_letter_requested_at_idx on payments.dead_letter (requested_at);
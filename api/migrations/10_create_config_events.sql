@@ -0,0 +1,8 @@
+create table if not exists payments.config_events (
+    id bigserial primary key,
+    event_type text not null,
+    occurred_at timestamptz not null default now(),
+    detail jsonb not null default '{}'
+);
+
+create index if not exists config_events_occurred_at_idx on payments.config_events (occurred_at);
@@ -0,0 +1 @@
+alter table payments.log add column if not exists callback_url text;
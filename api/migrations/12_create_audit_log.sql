@@ -0,0 +1,9 @@
+create table if not exists payments.audit_log (
+    id serial primary key,
+    payment_id uuid not null,
+    event text not null,
+    detail jsonb not null default '{}',
+    occurred_at timestamptz not null default now()
+);
+
+create index if not exists audit_log_payment_id_idx on payments.audit_log (payment_id);
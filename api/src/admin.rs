@@ -0,0 +1,282 @@
+use axum::{
+    Json,
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{sync::atomic, time::Instant};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::{
+    PENDING_SHRINK, PostPaymentDto, config, config_events, error::ApiError, in_flight_permits,
+    max_in_flight, processor::Payment, validate_payment,
+};
+
+const MAX_IN_FLIGHT_RANGE: std::ops::RangeInclusive<usize> = 1..=10_000;
+const MAX_SIMULATED_PAYMENTS: usize = 1_000_000;
+
+/// admin endpoints expect `Authorization: Bearer <admin_token>`,
+/// and are disabled altogether when no token is configured.
+/// compared in constant time, so response times don't leak how much of a guess was right
+pub fn authorized(headers: &HeaderMap) -> bool {
+    let Some(token) = config().admin_token.as_deref() else {
+        return false;
+    };
+
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|bearer| bool::from(bearer.as_bytes().ct_eq(token.as_bytes())))
+}
+
+/// permits held by in flight payments can't be taken back, so shrinking forgets the idle ones
+/// and leaves the rest for the payments to forget as they finish, growing first cancels
+/// whatever is still pending
+fn resize_in_flight_permits(previous: usize, current: usize) {
+    if current >= previous {
+        let grown = current - previous;
+        let cancelled = PENDING_SHRINK
+            .fetch_update(
+                atomic::Ordering::SeqCst,
+                atomic::Ordering::SeqCst,
+                |pending| Some(pending.saturating_sub(grown)),
+            )
+            .unwrap_or_default()
+            .min(grown);
+        in_flight_permits().add_permits(grown - cancelled);
+        return;
+    }
+
+    let shrunk = previous - current;
+    let forgotten = in_flight_permits().forget_permits(shrunk);
+    PENDING_SHRINK.fetch_add(shrunk - forgotten, atomic::Ordering::SeqCst);
+}
+
+#[derive(Deserialize)]
+pub struct ConfigValueDto {
+    pub value: usize,
+}
+
+#[derive(Serialize)]
+pub struct ConfigChangeDto {
+    pub previous: usize,
+    pub current: usize,
+    pub applied_at: DateTime<Utc>,
+}
+
+pub async fn patch_max_in_flight(
+    headers: HeaderMap,
+    Json(dto): Json<ConfigValueDto>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !authorized(&headers) {
+        return Err(ApiError::Unauthorized);
+    }
+
+    if !MAX_IN_FLIGHT_RANGE.contains(&dto.value) {
+        return Err(ApiError::InvalidInput(format!(
+            "value must be between {} and {}",
+            MAX_IN_FLIGHT_RANGE.start(),
+            MAX_IN_FLIGHT_RANGE.end()
+        )));
+    }
+
+    let previous = max_in_flight().swap(dto.value, atomic::Ordering::Relaxed);
+    resize_in_flight_permits(previous, dto.value);
+    tracing::warn!("max_in_flight changed from {} to {}", previous, dto.value);
+    tokio::spawn(config_events::record(
+        "max_in_flight_changed",
+        json!({ "previous": previous, "current": dto.value }),
+    ));
+
+    Ok((
+        StatusCode::OK,
+        Json(ConfigChangeDto {
+            previous,
+            current: dto.value,
+            applied_at: Utc::now(),
+        }),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct SimulateDto {
+    pub count: usize,
+    pub amount_min: Decimal,
+    pub amount_max: Decimal,
+}
+
+#[derive(Serialize)]
+pub struct SimulationResultDto {
+    pub simulated: usize,
+    pub duration_ms: u128,
+}
+
+/// generates payments without touching the db or the external processors,
+/// useful to measure the api overhead on its own
+pub async fn simulate_payments(
+    headers: HeaderMap,
+    Json(dto): Json<SimulateDto>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !authorized(&headers) {
+        return Err(ApiError::Unauthorized);
+    }
+
+    if dto.count > MAX_SIMULATED_PAYMENTS {
+        return Err(ApiError::InvalidInput(format!(
+            "count must be at most {}",
+            MAX_SIMULATED_PAYMENTS
+        )));
+    }
+    if dto.amount_min > dto.amount_max {
+        return Err(ApiError::InvalidInput(
+            "amount_min must not exceed amount_max".to_string(),
+        ));
+    }
+
+    // every amount is drawn between the two, so they're valid whenever both ends are
+    for amount in [dto.amount_min, dto.amount_max] {
+        let bound = PostPaymentDto {
+            correlation_id: Uuid::new_v4(),
+            amount,
+            timeout_secs: None,
+            callback_url: None,
+        };
+        if let Err(violations) = validate_payment(&bound) {
+            return Err(ApiError::ValidationFailed(violations));
+        }
+    }
+
+    let started = Instant::now();
+    let simulated = tokio::task::spawn_blocking(move || {
+        let mut rng = rand::rng();
+        let min_cents = cents(dto.amount_min);
+        let max_cents = cents(dto.amount_max);
+
+        (0..dto.count)
+            .map(|_| Payment {
+                correlation_id: Uuid::new_v4(),
+                amount: Decimal::new(rng.random_range(min_cents..=max_cents), 2),
+                requested_at: Utc::now(),
+                source_ip: None,
+                timeout_secs: None,
+                requeued: false,
+                callback_url: None,
+            })
+            .count()
+    })
+    .await
+    .map_err(|error| {
+        tracing::error!("failed simulating payments, {}", error);
+        ApiError::InternalError("failed simulating payments".to_string())
+    })?;
+
+    Ok((
+        StatusCode::OK,
+        Json(SimulationResultDto {
+            simulated,
+            duration_ms: started.elapsed().as_millis(),
+        }),
+    ))
+}
+
+fn cents(amount: Decimal) -> i64 {
+    (amount * Decimal::ONE_HUNDRED)
+        .trunc()
+        .try_into()
+        .unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        in_flight, sender,
+        test_support::{self, ADMIN_TOKEN, DEFAULT, patch_max_in_flight, payment, until},
+    };
+
+    async fn queue(payments: usize) {
+        for _ in 0..payments {
+            sender().send_async(payment()).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn max_in_flight_of_one_processes_payments_one_at_a_time() {
+        test_support::run(async {
+            assert_eq!(patch_max_in_flight(1).await, StatusCode::OK);
+            DEFAULT.set_delay(Duration::from_millis(50));
+
+            queue(4).await;
+            until(|| DEFAULT.received().len() == 4 && in_flight() == 0).await;
+            let peak = DEFAULT.peak_in_flight();
+            assert_eq!(patch_max_in_flight(16).await, StatusCode::OK);
+
+            assert_eq!(peak, 1);
+        })
+    }
+
+    #[test]
+    fn in_flight_counts_the_payments_a_pending_shrink_waits_on() {
+        test_support::run(async {
+            DEFAULT.set_delay(Duration::from_millis(300));
+            queue(3).await;
+            until(|| DEFAULT.received().len() == 3).await;
+
+            assert_eq!(patch_max_in_flight(1).await, StatusCode::OK);
+            assert_eq!(in_flight(), 3);
+
+            until(|| in_flight() == 0).await;
+            assert_eq!(in_flight_permits().available_permits(), 1);
+            assert_eq!(patch_max_in_flight(16).await, StatusCode::OK);
+            assert_eq!(in_flight_permits().available_permits(), 16);
+        })
+    }
+
+    #[test]
+    fn growing_cancels_what_a_shrink_has_yet_to_take_back() {
+        test_support::run(async {
+            DEFAULT.set_delay(Duration::from_millis(300));
+            queue(3).await;
+            until(|| DEFAULT.received().len() == 3).await;
+
+            assert_eq!(patch_max_in_flight(1).await, StatusCode::OK);
+            assert_eq!(patch_max_in_flight(16).await, StatusCode::OK);
+            assert_eq!(in_flight(), 3);
+
+            until(|| in_flight() == 0).await;
+            assert_eq!(in_flight_permits().available_permits(), 16);
+        })
+    }
+
+    #[test]
+    fn authorized_takes_the_exact_bearer_token_only() {
+        let cases = [
+            (Some(format!("Bearer {}", ADMIN_TOKEN)), true),
+            (Some("Bearer test-tokem".to_string()), false),
+            (Some("Bearer test-toke".to_string()), false),
+            (Some("Bearer test-token2".to_string()), false),
+            (Some("Bearer ".to_string()), false),
+            (Some(format!("Basic {}", ADMIN_TOKEN)), false),
+            (Some(ADMIN_TOKEN.to_string()), false),
+            (None, false),
+        ];
+
+        test_support::run(async {
+            for (authorization, expected) in cases {
+                let mut headers = HeaderMap::new();
+                if let Some(authorization) = &authorization {
+                    headers.insert(AUTHORIZATION, authorization.parse().unwrap());
+                }
+                assert_eq!(authorized(&headers), expected, "{:?}", authorization);
+            }
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use axum::{Json, response::IntoResponse};
+use serde_json::{Map, json};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{config, external_processors};
+
+/// shared by every clone of the processor's config, so all payments see the same state
+#[derive(Clone, Debug, Default)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<CircuitBreakerState>>,
+}
+
+#[derive(Debug)]
+pub enum CircuitBreakerState {
+    Closed {
+        consecutive_failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// a single probe is let through, and another one only if it never reports back
+    HalfOpen {
+        probing_since: Instant,
+    },
+}
+
+impl Default for CircuitBreakerState {
+    fn default() -> Self {
+        CircuitBreakerState::Closed {
+            consecutive_failures: 0,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// whether a request may be sent to the processor right now
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        match *state {
+            CircuitBreakerState::Closed { .. } => true,
+            CircuitBreakerState::Open { until } if now < until => false,
+            CircuitBreakerState::HalfOpen { probing_since }
+                if now - probing_since < open_duration() =>
+            {
+                false
+            }
+            CircuitBreakerState::Open { .. } | CircuitBreakerState::HalfOpen { .. } => {
+                *state = CircuitBreakerState::HalfOpen { probing_since: now };
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = CircuitBreakerState::default();
+    }
+
+    /// `true` when this failure is the one that opened the circuit
+    pub fn record_failure(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let was_open = matches!(*state, CircuitBreakerState::Open { .. });
+
+        *state = match *state {
+            CircuitBreakerState::Closed {
+                consecutive_failures,
+            } if consecutive_failures + 1 < config().failure_threshold => {
+                CircuitBreakerState::Closed {
+                    consecutive_failures: consecutive_failures + 1,
+                }
+            }
+            _ => CircuitBreakerState::Open {
+                until: Instant::now() + open_duration(),
+            },
+        };
+
+        !was_open && matches!(*state, CircuitBreakerState::Open { .. })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let now = Instant::now();
+
+        match *self.state.lock().unwrap() {
+            CircuitBreakerState::Closed {
+                consecutive_failures,
+            } => json!({ "state": "closed", "consecutiveFailures": consecutive_failures }),
+            CircuitBreakerState::Open { until } if now < until => json!({
+                "state": "open",
+                "retryInMillis": (until - now).as_millis() as u64,
+            }),
+            CircuitBreakerState::Open { .. } | CircuitBreakerState::HalfOpen { .. } => {
+                json!({ "state": "half_open" })
+            }
+        }
+    }
+}
+
+fn open_duration() -> Duration {
+    Duration::from_secs(config().open_duration_secs)
+}
+
+pub async fn health() -> impl IntoResponse {
+    let breakers: Map<_, _> = external_processors()
+        .iter()
+        .map(|processor| (processor.name.clone(), processor.breaker.to_json()))
+        .collect();
+
+    Json(breakers)
+}
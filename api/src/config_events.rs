@@ -0,0 +1,47 @@
+use serde_json::json;
+use sqlx::types::JsonValue;
+
+use crate::{db, external_processors, repository};
+
+const CONFIG_LOADED: &str = "config_loaded";
+
+/// events are only context for the summary, losing one isn't worth retrying over
+pub async fn record(event_type: &str, detail: JsonValue) {
+    if let Err(error) = repository::record_config_event(db(), event_type, &detail).await {
+        tracing::error!("failed recording config event {}, {}", event_type, error);
+    }
+}
+
+/// compares the configured processors with the ones the last startup loaded, there's no other
+/// way for them to change
+pub async fn record_startup() {
+    let current: Vec<&str> = external_processors()
+        .iter()
+        .map(|processor| processor.name.as_str())
+        .collect();
+
+    let previous: Vec<String> = match repository::last_config_event(db(), CONFIG_LOADED).await {
+        Ok(detail) => detail
+            .and_then(|detail| serde_json::from_value(detail["processors"].clone()).ok())
+            .unwrap_or_default(),
+        Err(error) => {
+            tracing::error!("failed fetching last loaded config, {}", error);
+            return;
+        }
+    };
+
+    for name in current
+        .iter()
+        .filter(|name| !previous.iter().any(|loaded| loaded == *name))
+    {
+        record("processor_added", json!({ "processor": name })).await;
+    }
+    for name in previous
+        .iter()
+        .filter(|name| !current.contains(&name.as_str()))
+    {
+        record("processor_removed", json!({ "processor": name })).await;
+    }
+
+    record(CONFIG_LOADED, json!({ "processors": current })).await;
+}
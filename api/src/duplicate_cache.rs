@@ -0,0 +1,46 @@
+use lru::LruCache;
+use std::{
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// correlation ids recently seen in the log, so replays are turned away without an insert,
+/// the least recently seen are evicted once it's full, and a zero size disables it
+#[derive(Debug)]
+pub struct DuplicateCache {
+    ttl: Duration,
+    entries: Option<Mutex<LruCache<Uuid, Instant>>>,
+}
+
+impl DuplicateCache {
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        DuplicateCache {
+            ttl,
+            entries: NonZeroUsize::new(size).map(|size| Mutex::new(LruCache::new(size))),
+        }
+    }
+
+    pub fn contains(&self, id: Uuid) -> bool {
+        let Some(entries) = &self.entries else {
+            return false;
+        };
+
+        let mut entries = entries.lock().unwrap();
+        match entries.get(&id) {
+            Some(seen_at) if seen_at.elapsed() < self.ttl => true,
+            Some(_) => {
+                entries.pop(&id);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&self, id: Uuid) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().put(id, Instant::now());
+        }
+    }
+}
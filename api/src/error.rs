@@ -0,0 +1,94 @@
+use axum::{
+    Json,
+    http::{StatusCode, header::RETRY_AFTER},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+/// errors handlers hand back to clients, always as `{"error": "...", "code": "..."}`
+/// so they can tell the reasons apart without parsing the message
+pub enum ApiError {
+    QueueFull {
+        retry_after_secs: u64,
+    },
+    RateLimited {
+        retry_after_secs: u64,
+    },
+    InvalidInput(String),
+    /// missing or wrong admin token
+    Unauthorized,
+    NotFound(String),
+    /// every constraint the input broke, not just the first
+    ValidationFailed(Vec<String>),
+    PayloadTooLarge(String),
+    InternalError(String),
+    DatabaseUnavailable,
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::QueueFull { .. } | ApiError::RateLimited { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::ValidationFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::QueueFull { .. } => "queue_full",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::InvalidInput(_) => "invalid_input",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::ValidationFailed(_) => "validation_failed",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::InternalError(_) => "internal_error",
+            ApiError::DatabaseUnavailable => "database_unavailable",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::QueueFull { .. } => "queue full",
+            ApiError::RateLimited { .. } => "too many requests",
+            ApiError::ValidationFailed(_) => "invalid payment",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::InvalidInput(message)
+            | ApiError::NotFound(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::InternalError(message) => message,
+            ApiError::DatabaseUnavailable => "database unavailable",
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = json!({ "error": self.message(), "code": self.code() });
+        if let ApiError::ValidationFailed(violations) = &self {
+            body["violations"] = json!(violations);
+        }
+        let body = Json(body);
+
+        match self {
+            // the processor is behind, the client should back off for about one drain cycle,
+            // or the client is over its rate and should wait for its next token
+            ApiError::QueueFull { retry_after_secs }
+            | ApiError::RateLimited { retry_after_secs } => (
+                self.status(),
+                [(RETRY_AFTER, retry_after_secs.to_string())],
+                body,
+            )
+                .into_response(),
+            _ => (self.status(), body).into_response(),
+        }
+    }
+}
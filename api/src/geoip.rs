@@ -0,0 +1,19 @@
+use chrono_tz::Tz;
+use maxminddb::{Reader, geoip2};
+use std::net::IpAddr;
+
+/// iso code of the country the ip is located in, `None` when the database doesn't know it
+pub fn country(reader: &Reader<Vec<u8>>, ip: IpAddr) -> Option<String> {
+    let lookup = reader.lookup(ip).ok()?;
+    let country: geoip2::Country = lookup.decode().ok()??;
+
+    country.country.iso_code.map(str::to_string)
+}
+
+/// time zone the ip is located in, only city databases have it
+pub fn time_zone(reader: &Reader<Vec<u8>>, ip: IpAddr) -> Option<Tz> {
+    let lookup = reader.lookup(ip).ok()?;
+    let city: geoip2::City = lookup.decode().ok()??;
+
+    city.location.time_zone?.parse().ok()
+}
@@ -0,0 +1,39 @@
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+
+use crate::repository;
+
+const READY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// liveness, answers as long as the process is up and serving
+pub async fn health() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+/// readiness, only when the db answers within `READY_TIMEOUT`
+pub async fn ready(State(db): State<Pool<Postgres>>) -> impl IntoResponse {
+    if database_reachable(&db).await {
+        return (StatusCode::OK, Json(json!({ "status": "ok" })));
+    }
+
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "status": "degraded", "reason": "database unreachable" })),
+    )
+}
+
+async fn database_reachable(db: &Pool<Postgres>) -> bool {
+    match tokio::time::timeout(READY_TIMEOUT, repository::ping(db)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(error)) => {
+            tracing::warn!("readiness check failed, {}", error);
+            false
+        }
+        Err(_) => {
+            tracing::warn!("readiness check timed out");
+            false
+        }
+    }
+}
@@ -1,30 +1,145 @@
 use axum::{
     Json, Router,
-    extract::Query,
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    extract::{ConnectInfo, Request, rejection::JsonRejection},
+    handler::Handler,
+    http::{HeaderMap, StatusCode},
+    middleware::from_fn,
+    response::{IntoResponse, Response},
+    routing::{get, patch, post},
 };
-use chrono::{DateTime, Utc};
-use flume::Sender;
+use chrono::Utc;
+use flume::{Sender, TrySendError};
+use governor::DefaultKeyedRateLimiter;
 use serde::Deserialize;
-use simplelog::{CombinedLogger, LevelFilter, TermLogger, TerminalMode};
+use serde_json::json;
 use sqlx::{Pool, Postgres, postgres::PgPoolOptions, types::Decimal};
-use std::{env::var, net::SocketAddr, str::FromStr, sync::OnceLock, time::Duration};
+use std::{
+    collections::HashMap,
+    env::var,
+    net::{IpAddr, SocketAddr},
+    ops::Deref,
+    str::FromStr,
+    sync::{
+        Arc, OnceLock,
+        atomic::{self, AtomicUsize},
+    },
+    time::Duration,
+};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::Semaphore,
+};
+use tokio_util::sync::CancellationToken;
+use tower_http::trace::TraceLayer;
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
 use uuid::Uuid;
 
-use crate::processor::{Payment, Processor};
+use crate::{
+    circuit_breaker::CircuitBreaker,
+    duplicate_cache::DuplicateCache,
+    error::ApiError,
+    metrics::Metrics,
+    processor::{Payment, Processor},
+    summary_cache::SummaryCache,
+};
 
+mod admin;
+mod circuit_breaker;
+mod config_events;
+mod duplicate_cache;
+mod error;
+mod geoip;
+mod health;
+mod metrics;
+mod payments;
 mod processor;
+mod rate_limit;
 mod repository;
+mod signature;
+mod summary;
+mod summary_cache;
+#[cfg(test)]
+mod test_support;
+mod time_series;
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct ProcessorConfig {
     pub name: String,
     pub endpoint: String,
+    #[serde(default)]
+    pub slo_max_latency_ms: Option<u64>,
+    /// relative share of payments sent here first, a processor with weight 3 gets about three
+    /// times as many as one with weight 1, and one with weight 0 is only a fallback
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// overrides for the shared client's timeouts, either one gets this processor a client of
+    /// its own
+    #[serde(default)]
+    pub timeout_millis: Option<u64>,
+    #[serde(default)]
+    pub connect_timeout_millis: Option<u64>,
+    #[serde(skip)]
+    pub breaker: CircuitBreaker,
+}
+
+/// a processor's config along with the client its requests go through
+#[derive(Debug)]
+pub struct ProcessorRuntime {
+    pub config: ProcessorConfig,
+    client: Option<reqwest::Client>,
+}
+
+impl ProcessorRuntime {
+    fn new(config: ProcessorConfig) -> Self {
+        let client = (config.timeout_millis.is_some() || config.connect_timeout_millis.is_some())
+            .then(|| {
+                http_client_builder(
+                    config
+                        .timeout_millis
+                        .map_or(HTTP_TIMEOUT, Duration::from_millis),
+                    config
+                        .connect_timeout_millis
+                        .map_or(HTTP_TIMEOUT, Duration::from_millis),
+                )
+                .build()
+                .unwrap()
+            });
+
+        ProcessorRuntime { config, client }
+    }
+
+    /// the shared client unless the processor has timeouts of its own
+    pub fn client(&self) -> &reqwest::Client {
+        match &self.client {
+            Some(client) => client,
+            None => http_client(),
+        }
+    }
+}
+
+impl Deref for ProcessorRuntime {
+    type Target = ProcessorConfig;
+
+    fn deref(&self) -> &ProcessorConfig {
+        &self.config
+    }
+}
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn http_client_builder(timeout: Duration, connect_timeout: Duration) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .connect_timeout(connect_timeout)
+        .pool_max_idle_per_host(500)
+}
+
+fn default_weight() -> u32 {
+    1
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct Config {
     pub listen_port: u16,
     pub database_url: String,
@@ -32,71 +147,382 @@ pub struct Config {
     pub max_in_flight: usize,
     pub max_wait_millis: usize,
     pub external_processors: Vec<ProcessorConfig>,
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    #[serde(default)]
+    pub hide_endpoint_urls: bool,
+    #[serde(default = "default_table_size_check_interval_secs")]
+    pub table_size_check_interval_secs: u64,
+    #[serde(default)]
+    pub pool_min_connections: u32,
+    #[serde(default)]
+    pub pool_max_lifetime_secs: Option<u64>,
+    #[serde(default)]
+    pub pool_idle_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub enable_debug_endpoints: bool,
+    #[serde(default)]
+    pub privacy_shuffle_seed: Option<u64>,
+    #[serde(default)]
+    pub mask_ips: bool,
+    #[serde(default)]
+    pub summary_cache_ttl_millis: u64,
+    #[serde(default)]
+    pub geoip_db_path: Option<String>,
+    #[serde(default = "default_queue_capacity")]
+    pub queue_capacity: usize,
+    #[serde(default = "default_retry_base_millis")]
+    pub retry_base_millis: u64,
+    #[serde(default = "default_retry_cap_millis")]
+    pub retry_cap_millis: u64,
+    /// logical processor name for each processor that's split across several entries
+    #[serde(default)]
+    pub processor_aliases: HashMap<String, String>,
+    /// consecutive failures before a processor's circuit opens and it gets skipped
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    #[serde(default = "default_open_duration_secs")]
+    pub open_duration_secs: u64,
+    #[serde(default = "default_batch_max_size")]
+    pub batch_max_size: usize,
+    /// failed attempts retried before a payment is dead lettered, 0 retries forever
+    #[serde(default)]
+    pub max_retries: usize,
+    /// retries go back to the processor picked first instead of picking again
+    #[serde(default)]
+    pub sticky_on_retry: bool,
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// correlation ids kept in memory to turn duplicates away before the db, 0 disables it
+    #[serde(default = "default_dedup_cache_size")]
+    pub dedup_cache_size: usize,
+    #[serde(default = "default_dedup_ttl_secs")]
+    pub dedup_ttl_secs: u64,
+    /// payments waiting in the queue go into the log in a single insert, off takes them one
+    /// at a time, which is easier to follow when debugging
+    #[serde(default = "default_true")]
+    pub use_batch_insert: bool,
+    /// off when the schema is managed elsewhere
+    #[serde(default = "default_true")]
+    pub run_migrations: bool,
+    #[serde(default = "default_shutdown_drain_timeout_secs")]
+    pub shutdown_drain_timeout_secs: u64,
+    /// failed callbacks retried before giving up on notifying the client
+    #[serde(default = "default_callback_max_retries")]
+    pub callback_max_retries: usize,
+    /// hosts a callback_url may point at even when they resolve to a private address, like a
+    /// sidecar on the same network, matched against the url's host as written
+    #[serde(default)]
+    pub callback_allowed_hosts: Vec<String>,
+    /// requests per second each client ip gets on the payment posts, 0 doesn't limit them
+    #[serde(default)]
+    pub rate_per_second: u32,
+    /// requests a client can make back to back before the rate kicks in, 0 is one second's worth
+    #[serde(default)]
+    pub burst_size: u32,
+    /// payments above it are turned away with a 422
+    #[serde(default = "default_max_payment_amount")]
+    pub max_payment_amount: Decimal,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// one processor per attempt, picked by weight
+    #[default]
+    Sequential,
+    /// every processor with a closed circuit at once, the first to accept wins, the others may
+    /// still process the payment before they're aborted, so this trades duplicates for latency
+    Race,
 }
 
+fn default_table_size_check_interval_secs() -> u64 {
+    60
+}
+
+fn default_queue_capacity() -> usize {
+    10_000
+}
+
+fn default_batch_max_size() -> usize {
+    1000
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_shutdown_drain_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_payment_amount() -> Decimal {
+    Decimal::from(1_000_000)
+}
+
+fn default_callback_max_retries() -> usize {
+    3
+}
+
+fn default_dedup_cache_size() -> usize {
+    100_000
+}
+
+fn default_dedup_ttl_secs() -> u64 {
+    300
+}
+
+fn default_retry_base_millis() -> u64 {
+    5
+}
+
+fn default_retry_cap_millis() -> u64 {
+    1_000
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_duration_secs() -> u64 {
+    5
+}
+
+pub static CONFIG: OnceLock<Config> = OnceLock::new();
 pub static DB: OnceLock<Pool<Postgres>> = OnceLock::new();
-pub static EXTERNAL_PROCESSORS: OnceLock<Vec<ProcessorConfig>> = OnceLock::new();
+pub static EXTERNAL_PROCESSORS: OnceLock<Vec<ProcessorRuntime>> = OnceLock::new();
 pub static SENDER: OnceLock<Sender<Payment>> = OnceLock::new();
 pub static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+/// doesn't follow redirects, they could bounce a callback off to an address its url was never
+/// checked against
+pub static CALLBACK_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+pub static METRICS: OnceLock<Arc<Metrics>> = OnceLock::new();
+pub static MAX_IN_FLIGHT: OnceLock<AtomicUsize> = OnceLock::new();
+pub static IN_FLIGHT_PERMITS: OnceLock<Arc<Semaphore>> = OnceLock::new();
+/// permits a shrink of `MAX_IN_FLIGHT` still has to take back from in flight payments
+pub static PENDING_SHRINK: AtomicUsize = AtomicUsize::new(0);
+pub static GEOIP: OnceLock<maxminddb::Reader<Vec<u8>>> = OnceLock::new();
+pub static SUMMARY_CACHE: OnceLock<SummaryCache> = OnceLock::new();
+pub static DEDUP_CACHE: OnceLock<DuplicateCache> = OnceLock::new();
+pub static RATE_LIMITER: OnceLock<DefaultKeyedRateLimiter<IpAddr>> = OnceLock::new();
+/// each processor's index repeated `weight` times, sampled uniformly to pick a processor
+pub static PROCESSOR_SLOTS: OnceLock<Vec<usize>> = OnceLock::new();
 
 #[tokio::main]
 async fn main() {
     let config = var("APP_CONFIG").unwrap();
     let config: Config = serde_json::from_str(&config).unwrap();
 
-    let log_level = LevelFilter::from_str(&config.log_level).unwrap_or_else(|_| LevelFilter::Info);
-    CombinedLogger::init(vec![TermLogger::new(
-        log_level,
-        simplelog::Config::default(),
-        TerminalMode::Mixed,
-        simplelog::ColorChoice::Always,
-    )])
-    .unwrap();
+    // RUST_LOG takes precedence, for narrowing it down to a module without touching the config
+    let log_level = tracing::Level::from_str(&config.log_level).unwrap_or(tracing::Level::INFO);
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(log_level.as_str())),
+        )
+        .init();
 
-    let (sender, receiver) = flume::unbounded();
+    let mut pool_options = PgPoolOptions::new()
+        .max_connections(32)
+        .min_connections(config.pool_min_connections);
 
-    DB.set(
-        PgPoolOptions::new()
-            .max_connections(32)
-            .connect(&config.database_url)
-            .await
-            .unwrap(),
+    if let Some(secs) = config.pool_max_lifetime_secs {
+        pool_options = pool_options.max_lifetime(Duration::from_secs(secs));
+    }
+
+    if let Some(secs) = config.pool_idle_timeout_secs {
+        pool_options = pool_options.idle_timeout(Duration::from_secs(secs));
+    }
+
+    DB.set(pool_options.connect(&config.database_url).await.unwrap())
+        .unwrap();
+
+    // every instance runs them, postgres' advisory lock lets only one apply each
+    if config.run_migrations {
+        sqlx::migrate!("./migrations").run(db()).await.unwrap();
+    }
+
+    let mut processor = init(config.clone());
+
+    tokio::spawn(config_events::record_startup());
+
+    let shutdown = CancellationToken::new();
+    let processor_shutdown = shutdown.clone();
+    tokio::spawn(async move { processor.run_forever(processor_shutdown).await });
+
+    if rate_limiter().is_some() {
+        tokio::spawn(rate_limit::prune_forever(Duration::from_secs(60)));
+    }
+
+    tokio::spawn(metrics::refresh_table_size_forever(Duration::from_secs(
+        config.table_size_check_interval_secs,
+    )));
+
+    let addr = SocketAddr::new([0, 0, 0, 0].into(), config.listen_port);
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+
+    axum::serve(
+        listener,
+        router().into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(shutdown_signal(shutdown))
+    .await
     .unwrap();
 
-    EXTERNAL_PROCESSORS.set(config.external_processors).unwrap();
+    drain_in_flight(Duration::from_secs(config.shutdown_drain_timeout_secs)).await;
+}
+
+/// sets every static but `DB`, which has to be set before, and hands back the processor that
+/// takes payments off the queue, for the caller to run
+fn init(config: Config) -> Processor {
+    let (sender, receiver) = flume::bounded(config.queue_capacity);
+
+    CONFIG.set(config.clone()).unwrap();
+    METRICS.set(Arc::new(Metrics::new())).unwrap();
+    SUMMARY_CACHE
+        .set(SummaryCache::new(Duration::from_millis(
+            config.summary_cache_ttl_millis,
+        )))
+        .unwrap();
+    PROCESSOR_SLOTS
+        .set(
+            config
+                .external_processors
+                .iter()
+                .enumerate()
+                .flat_map(|(index, processor)| {
+                    std::iter::repeat_n(index, processor.weight as usize)
+                })
+                .collect(),
+        )
+        .unwrap();
+    DEDUP_CACHE
+        .set(DuplicateCache::new(
+            config.dedup_cache_size,
+            Duration::from_secs(config.dedup_ttl_secs),
+        ))
+        .unwrap();
+
+    if let Some(path) = &config.geoip_db_path {
+        match maxminddb::Reader::open_readfile(path) {
+            Ok(reader) => GEOIP.set(reader).unwrap(),
+            Err(error) => tracing::error!("failed opening geoip database {}, {}", path, error),
+        }
+    }
 
     HTTP_CLIENT
         .set(
-            reqwest::Client::builder()
-                .timeout(Duration::from_secs(2))
-                .connect_timeout(Duration::from_secs(2))
-                .pool_max_idle_per_host(500)
+            http_client_builder(HTTP_TIMEOUT, HTTP_TIMEOUT)
                 .build()
                 .unwrap(),
         )
         .unwrap();
+    CALLBACK_CLIENT
+        .set(
+            http_client_builder(HTTP_TIMEOUT, HTTP_TIMEOUT)
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+    EXTERNAL_PROCESSORS
+        .set(
+            config
+                .external_processors
+                .into_iter()
+                .map(ProcessorRuntime::new)
+                .collect(),
+        )
+        .unwrap();
 
     SENDER.set(sender).unwrap();
 
-    tokio::spawn(async move {
-        let mut processor = Processor {
-            receiver,
-            max_in_flight: config.max_in_flight,
-            max_wait_millis: config.max_wait_millis,
-        };
+    MAX_IN_FLIGHT
+        .set(AtomicUsize::new(config.max_in_flight))
+        .unwrap();
+    let in_flight_permits = Arc::new(Semaphore::new(config.max_in_flight));
+    IN_FLIGHT_PERMITS.set(in_flight_permits.clone()).unwrap();
 
-        processor.run_forever().await
-    });
+    if let Some(limiter) = rate_limit::new(config.rate_per_second, config.burst_size) {
+        RATE_LIMITER.set(limiter).unwrap();
+    }
 
-    let addr = SocketAddr::new([0, 0, 0, 0].into(), config.listen_port);
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    Processor {
+        receiver,
+        in_flight_permits,
+    }
+}
 
-    let app = Router::new()
-        .route("/payments", post(new_payment))
-        .route("/payments-summary", get(summary));
+fn router() -> Router {
+    Router::new()
+        .route(
+            "/payments",
+            post(new_payment.layer(from_fn(rate_limit::limit))).get(payments::list_payments),
+        )
+        .route(
+            "/payments/batch",
+            post(new_payment_batch.layer(from_fn(rate_limit::limit))),
+        )
+        .route("/payments/dead-letter", get(payments::dead_letters))
+        .route("/payments/{id}/requeue", post(payments::requeue))
+        .route("/payments/{id}/audit", get(payments::audit_log))
+        .route("/payments/simulate", post(admin::simulate_payments))
+        .route("/payments/{id}", get(payments::get_payment))
+        .route("/payments-summary", get(summary::summary))
+        .route("/metrics", get(metrics::scrape))
+        .route("/processors/health", get(circuit_breaker::health))
+        .route("/config/max-in-flight", patch(admin::patch_max_in_flight))
+        .route("/health", get(health::health))
+        .route("/ready", get(health::ready))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &Request<_>| {
+                    tracing::info_span!(
+                        "request",
+                        method = %request.method(),
+                        path = request.uri().path(),
+                        status = tracing::field::Empty,
+                        latency_ms = tracing::field::Empty,
+                    )
+                })
+                .on_response(|response: &Response, latency: Duration, span: &Span| {
+                    span.record("status", response.status().as_u16());
+                    span.record("latency_ms", latency.as_millis() as u64);
+                    tracing::debug!("finished processing request");
+                }),
+        )
+        .with_state(db().clone())
+}
 
-    axum::serve(listener, app).await.unwrap();
+/// resolves on sigterm or ctrl-c, cancelling `shutdown` so the processor stops taking payments
+async fn shutdown_signal(shutdown: CancellationToken) {
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+
+    tracing::warn!("shutting down, no longer accepting payments");
+    shutdown.cancel();
+}
+
+/// payments still queued are lost, the ones being processed get until `timeout` to finish
+async fn drain_in_flight(timeout: Duration) {
+    let drained = tokio::time::timeout(timeout, async {
+        while in_flight() > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+
+    if drained.is_err() {
+        tracing::error!("{} payments still in flight at shutdown", in_flight());
+    }
+    if !sender().is_empty() {
+        tracing::error!("{} queued payments dropped at shutdown", sender().len());
+    }
 }
 
 #[derive(Deserialize)]
@@ -104,61 +530,257 @@ async fn main() {
 pub struct PostPaymentDto {
     pub correlation_id: Uuid,
     pub amount: Decimal,
+    pub timeout_secs: Option<u64>,
+    pub callback_url: Option<String>,
 }
 
-async fn new_payment(Json(dto): Json<PostPaymentDto>) -> impl IntoResponse {
+/// every constraint the payment breaks, so the client can fix them all in one go
+fn validate_payment(dto: &PostPaymentDto) -> Result<(), Vec<String>> {
+    let mut violations = Vec::new();
+
+    if dto.amount <= Decimal::ZERO {
+        violations.push("amount must be positive".to_string());
+    }
+    if dto.amount.normalize().scale() > 2 {
+        violations.push("amount must have at most 2 decimal places".to_string());
+    }
+    if dto.amount > config().max_payment_amount {
+        violations.push(format!(
+            "amount must be at most {}",
+            config().max_payment_amount
+        ));
+    }
+    if dto.correlation_id.is_nil() || dto.correlation_id.get_version_num() != 4 {
+        violations.push("correlationId must be a uuid v4".to_string());
+    }
+
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err(violations),
+    }
+}
+
+/// only http and https to hosts that resolve to public addresses, unless they're in
+/// `callback_allowed_hosts`, anything else is turned away before the payment is queued
+async fn callback_url(url: Option<&str>) -> Result<Option<Arc<str>>, &'static str> {
+    const INVALID: &str = "invalid callback_url";
+    let Some(url) = url else {
+        return Ok(None);
+    };
+
+    let parsed = reqwest::Url::parse(url).map_err(|_| INVALID)?;
+    let (Some(host), Some(port)) = (parsed.host_str(), parsed.port_or_known_default()) else {
+        return Err(INVALID);
+    };
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(INVALID);
+    }
+    if config()
+        .callback_allowed_hosts
+        .iter()
+        .any(|allowed| allowed == host)
+    {
+        return Ok(Some(url.into()));
+    }
+
+    // a name has to be public on every address, the callback may go to any of them
+    let addresses: Vec<IpAddr> = match host.trim_matches(['[', ']']).parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => {
+            match tokio::time::timeout(HTTP_TIMEOUT, tokio::net::lookup_host((host, port))).await {
+                Ok(Ok(addresses)) => addresses.map(|address| address.ip()).collect(),
+                _ => return Err(INVALID),
+            }
+        }
+    };
+    match !addresses.is_empty() && addresses.into_iter().all(is_public) {
+        true => Ok(Some(url.into())),
+        false => Err(INVALID),
+    }
+}
+
+/// anything a callback could use to reach this host or the network it sits on
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            let shared = a == 100 && (64..128).contains(&b);
+            !(a == 0
+                || shared
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation())
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+async fn new_payment(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    dto: Result<Json<PostPaymentDto>, JsonRejection>,
+) -> Response {
+    let Json(dto) = match dto {
+        Ok(dto) => dto,
+        Err(rejection) => return ApiError::InvalidInput(rejection.body_text()).into_response(),
+    };
+    if let Err(violations) = validate_payment(&dto) {
+        return ApiError::ValidationFailed(violations).into_response();
+    }
+    let callback_url = match callback_url(dto.callback_url.as_deref()).await {
+        Ok(callback_url) => callback_url,
+        Err(violation) => return ApiError::InvalidInput(violation.to_string()).into_response(),
+    };
     let now = Utc::now();
     let payment = Payment {
         correlation_id: dto.correlation_id,
         amount: dto.amount,
         requested_at: now,
+        source_ip: Some(client_ip(&headers, peer)),
+        timeout_secs: dto.timeout_secs,
+        requeued: false,
+        callback_url,
     };
 
-    match sender().send(payment) {
-        Ok(_) => StatusCode::CREATED,
+    match sender().try_send(payment) {
+        Ok(_) => {
+            metrics().payments_received_total.inc();
+            StatusCode::CREATED.into_response()
+        }
+        Err(TrySendError::Full(_)) => queue_full().into_response(),
         Err(error) => {
-            log::error!("failed submitting to internal processor, {}", error);
-            StatusCode::INTERNAL_SERVER_ERROR
+            tracing::error!("failed submitting to internal processor, {}", error);
+            ApiError::InternalError("failed submitting payment".to_string()).into_response()
         }
     }
 }
 
-#[derive(Deserialize)]
-struct SummaryParams {
-    from: Option<DateTime<Utc>>,
-    to: Option<DateTime<Utc>>,
+/// the processor is behind, the client should back off for about one drain cycle
+fn queue_full() -> ApiError {
+    ApiError::QueueFull {
+        retry_after_secs: (config().max_wait_millis as u64).div_ceil(1000).max(1),
+    }
 }
 
-async fn summary(Query(window): Query<SummaryParams>) -> impl IntoResponse {
-    let from = window
-        .from
-        .unwrap_or_else(|| DateTime::<Utc>::from_str("0000-01-01T00:00:00.000Z").unwrap());
-    let to = window
-        .to
-        .unwrap_or_else(|| DateTime::<Utc>::from_str("9999-12-31T23:59:59.999Z").unwrap());
+/// enqueues in order and stops at the first full queue, so the accepted ones are always a prefix
+async fn new_payment_batch(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    dtos: Result<Json<Vec<PostPaymentDto>>, JsonRejection>,
+) -> Response {
+    let Json(dtos) = match dtos {
+        Ok(dtos) => dtos,
+        Err(rejection) => return ApiError::InvalidInput(rejection.body_text()).into_response(),
+    };
+    if dtos.len() > config().batch_max_size {
+        return ApiError::PayloadTooLarge(format!(
+            "at most {} payments per batch",
+            config().batch_max_size
+        ))
+        .into_response();
+    }
 
-    let processor_names = external_processors()
-        .iter()
-        .map(|p| p.name.clone())
-        .collect();
+    // a bad payment anywhere turns the whole batch away, before any of it is queued
+    let mut violations = Vec::new();
+    let mut callback_urls = Vec::with_capacity(dtos.len());
+    for (index, dto) in dtos.iter().enumerate() {
+        let mut found = validate_payment(dto).err().unwrap_or_default();
+        match callback_url(dto.callback_url.as_deref()).await {
+            Ok(callback_url) => callback_urls.push(callback_url),
+            Err(violation) => found.push(violation.to_string()),
+        }
+        violations.extend(
+            found
+                .into_iter()
+                .map(|violation| format!("[{}] {}", index, violation)),
+        );
+    }
+    if !violations.is_empty() {
+        return ApiError::ValidationFailed(violations).into_response();
+    }
 
-    match repository::summary(db(), &processor_names, from, to).await {
-        Ok(summary) => Ok((StatusCode::OK, Json(summary))),
-        Err(error) => {
-            log::error!("failed fetching summary, {}", error);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+    let now = Utc::now();
+    let source_ip = client_ip(&headers, peer);
+    let count = dtos.len();
+    let mut succeeded = Vec::with_capacity(count);
+
+    for (index, (dto, callback_url)) in dtos.into_iter().zip(callback_urls).enumerate() {
+        let payment = Payment {
+            correlation_id: dto.correlation_id,
+            amount: dto.amount,
+            requested_at: now,
+            source_ip: Some(source_ip),
+            timeout_secs: dto.timeout_secs,
+            requeued: false,
+            callback_url,
+        };
+
+        match sender().try_send(payment) {
+            Ok(_) => {
+                metrics().payments_received_total.inc();
+                succeeded.push(index);
+            }
+            Err(TrySendError::Full(_)) => break,
+            Err(error) => {
+                tracing::error!("failed submitting to internal processor, {}", error);
+                return ApiError::InternalError("failed submitting payment".to_string())
+                    .into_response();
+            }
         }
     }
+
+    if succeeded.len() == count {
+        return StatusCode::CREATED.into_response();
+    }
+
+    let rejected: Vec<_> = (succeeded.len()..count)
+        .map(|index| json!({ "index": index, "reason": "queue_full" }))
+        .collect();
+    (
+        StatusCode::MULTI_STATUS,
+        Json(json!({ "succeeded": succeeded, "rejected": rejected })),
+    )
+        .into_response()
+}
+
+/// the api sits behind nginx, so the peer is usually the proxy and the client is in
+/// `X-Forwarded-For`. nginx appends the address it saw after whatever the client sent, so only
+/// the last entry can be trusted
+fn client_ip(headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+    headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(peer.ip())
+}
+
+fn config() -> &'static Config {
+    unsafe { CONFIG.get().unwrap_unchecked() }
 }
 
 fn db() -> &'static Pool<Postgres> {
     unsafe { DB.get().unwrap_unchecked() }
 }
 
-fn external_processors() -> &'static Vec<ProcessorConfig> {
+fn external_processors() -> &'static Vec<ProcessorRuntime> {
     unsafe { EXTERNAL_PROCESSORS.get().unwrap_unchecked() }
 }
 
+fn processor_slots() -> &'static Vec<usize> {
+    unsafe { PROCESSOR_SLOTS.get().unwrap_unchecked() }
+}
+
 fn sender<'a>() -> &'a Sender<Payment> {
     unsafe { SENDER.get().unwrap_unchecked() }
 }
@@ -166,3 +788,302 @@ fn sender<'a>() -> &'a Sender<Payment> {
 fn http_client() -> &'static reqwest::Client {
     unsafe { HTTP_CLIENT.get().unwrap_unchecked() }
 }
+
+fn callback_client() -> &'static reqwest::Client {
+    unsafe { CALLBACK_CLIENT.get().unwrap_unchecked() }
+}
+
+fn max_in_flight() -> &'static AtomicUsize {
+    unsafe { MAX_IN_FLIGHT.get().unwrap_unchecked() }
+}
+
+fn in_flight_permits() -> &'static Arc<Semaphore> {
+    unsafe { IN_FLIGHT_PERMITS.get().unwrap_unchecked() }
+}
+
+/// payments holding a permit, the ones a pending shrink is still waiting on included
+fn in_flight() -> usize {
+    (max_in_flight().load(atomic::Ordering::Relaxed)
+        + PENDING_SHRINK.load(atomic::Ordering::SeqCst))
+    .saturating_sub(in_flight_permits().available_permits())
+}
+
+/// `None` when no geoip database is configured, or it couldn't be opened
+fn geoip_db() -> Option<&'static maxminddb::Reader<Vec<u8>>> {
+    GEOIP.get()
+}
+
+/// `None` when rate limiting is off
+fn rate_limiter() -> Option<&'static DefaultKeyedRateLimiter<IpAddr>> {
+    RATE_LIMITER.get()
+}
+
+fn metrics() -> &'static Metrics {
+    unsafe { METRICS.get().unwrap_unchecked() }
+}
+
+fn summary_cache() -> &'static SummaryCache {
+    unsafe { SUMMARY_CACHE.get().unwrap_unchecked() }
+}
+
+fn dedup_cache() -> &'static DuplicateCache {
+    unsafe { DEDUP_CACHE.get().unwrap_unchecked() }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            Request, StatusCode,
+            header::{AUTHORIZATION, RETRY_AFTER},
+        },
+    };
+    use chrono::TimeDelta;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{
+        self, ADMIN_TOKEN, CALLBACK_ALLOWED_HOST, MAX_WAIT_MILLIS, fill_queue, resume,
+    };
+
+    const V4: &str = "5f4dcb5c-1f2a-4c3b-9a7d-2e8f6b1c0d3e";
+    const V1: &str = "c232ab00-9414-11ec-b3c8-9f6bdeced846";
+    const V7: &str = "01890a5d-ac96-774b-bcce-b302099a8057";
+
+    fn dto(correlation_id: &str, amount: &str) -> PostPaymentDto {
+        PostPaymentDto {
+            correlation_id: correlation_id.parse().unwrap(),
+            amount: amount.parse().unwrap(),
+            timeout_secs: None,
+            callback_url: None,
+        }
+    }
+
+    #[test]
+    fn validate_payment_boundaries() {
+        let nil = Uuid::nil().to_string();
+        let max = "ffffffff-ffff-ffff-ffff-ffffffffffff";
+        let cases = [
+            (V4, "0.01", 0),
+            (V4, "19.90", 0),
+            (V4, "19.900", 0),
+            (V4, "1000000", 0),
+            (V4, "1000000.01", 1),
+            (V4, "0", 1),
+            (V4, "-0.01", 1),
+            (V4, "-10.001", 2),
+            (V4, "0.001", 1),
+            (V4, "10.123", 1),
+            (V1, "10", 1),
+            (V7, "10", 1),
+            (&nil, "10", 1),
+            (max, "10", 1),
+            (&nil, "0", 2),
+        ];
+
+        test_support::run(async {
+            for (correlation_id, amount, expected) in cases {
+                let violations = validate_payment(&dto(correlation_id, amount)).err();
+                assert_eq!(
+                    violations.as_ref().map_or(0, Vec::len),
+                    expected,
+                    "{} {}: {:?}",
+                    correlation_id,
+                    amount,
+                    violations
+                );
+            }
+        })
+    }
+
+    #[test]
+    fn simulate_payments_validates_both_ends_of_the_range() {
+        let cases = [
+            ("0.01", "1000000", StatusCode::OK),
+            ("0", "10", StatusCode::UNPROCESSABLE_ENTITY),
+            ("-1", "10", StatusCode::UNPROCESSABLE_ENTITY),
+            ("0.001", "10", StatusCode::UNPROCESSABLE_ENTITY),
+            ("1", "10.005", StatusCode::UNPROCESSABLE_ENTITY),
+            ("1", "1000000.01", StatusCode::UNPROCESSABLE_ENTITY),
+            ("10", "1", StatusCode::BAD_REQUEST),
+        ];
+
+        test_support::run(async {
+            for (amount_min, amount_max, expected) in cases {
+                let request = Request::post("/payments/simulate")
+                    .header(AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        json!({
+                            "count": 10,
+                            "amount_min": amount_min,
+                            "amount_max": amount_max,
+                        })
+                        .to_string(),
+                    ))
+                    .unwrap();
+                let response = test_support::send(request, "10.0.2.1:4000".parse().unwrap()).await;
+
+                assert_eq!(response.status(), expected, "{} {}", amount_min, amount_max);
+            }
+        })
+    }
+
+    #[test]
+    fn new_payment_stamps_requested_at_when_it_is_posted() {
+        test_support::run(async {
+            let id = Uuid::new_v4();
+            let request = Request::post("/payments")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    json!({ "correlationId": id, "amount": "19.90" }).to_string(),
+                ))
+                .unwrap();
+
+            // postgres keeps microseconds, so either end may be rounded past by one
+            let before = Utc::now() - TimeDelta::microseconds(1);
+            let response = test_support::send(request, "10.0.5.1:4000".parse().unwrap()).await;
+            let after = Utc::now() + TimeDelta::microseconds(1);
+            assert_eq!(response.status(), StatusCode::CREATED);
+
+            let payment = tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    if let Some(payment) = repository::get_payment(db(), id).await.unwrap() {
+                        return payment;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+
+            assert!(
+                (before..=after).contains(&payment.requested_at),
+                "{} not within {} and {}",
+                payment.requested_at,
+                before,
+                after
+            );
+        })
+    }
+
+    fn post(uri: &str, body: serde_json::Value) -> Request<Body> {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn posted(amount: &str) -> serde_json::Value {
+        json!({ "correlationId": Uuid::new_v4(), "amount": amount })
+    }
+
+    #[test]
+    fn new_payment_into_a_full_queue_is_told_to_come_back() {
+        test_support::run(async {
+            fill_queue(0).await;
+            let response = test_support::send(
+                post("/payments", posted("19.90")),
+                "10.0.6.1:4000".parse().unwrap(),
+            )
+            .await;
+            resume().await;
+
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            let retry_after = (MAX_WAIT_MILLIS as u64).div_ceil(1000).max(1);
+            assert_eq!(
+                response.headers()[RETRY_AFTER],
+                retry_after.to_string().as_str()
+            );
+            let body: serde_json::Value =
+                serde_json::from_slice(&test_support::body(response).await).unwrap();
+            assert_eq!(body, json!({ "error": "queue full", "code": "queue_full" }));
+        })
+    }
+
+    #[test]
+    fn new_payment_batch_queues_a_prefix_and_rejects_the_rest() {
+        test_support::run(async {
+            fill_queue(2).await;
+            let response = test_support::send(
+                post(
+                    "/payments/batch",
+                    json!([posted("1"), posted("2"), posted("3"), posted("4")]),
+                ),
+                "10.0.6.2:4000".parse().unwrap(),
+            )
+            .await;
+            resume().await;
+
+            assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+            let body: serde_json::Value =
+                serde_json::from_slice(&test_support::body(response).await).unwrap();
+            assert_eq!(
+                body,
+                json!({
+                    "succeeded": [0, 1],
+                    "rejected": [
+                        { "index": 2, "reason": "queue_full" },
+                        { "index": 3, "reason": "queue_full" },
+                    ],
+                })
+            );
+        })
+    }
+
+    #[test]
+    fn callback_url_only_reaches_public_addresses() {
+        let allowed = format!("http://{}:9000/done", CALLBACK_ALLOWED_HOST);
+        let cases = [
+            ("https://93.184.216.34/done", true),
+            ("http://[2606:4700::1111]:8080/done", true),
+            (allowed.as_str(), true),
+            ("ftp://93.184.216.34/done", false),
+            ("not a url", false),
+            ("http://127.0.0.1:9999/done", false),
+            ("http://localhost/done", false),
+            ("http://0.0.0.0/done", false),
+            ("http://10.1.2.3/done", false),
+            ("http://172.16.0.1/done", false),
+            ("http://192.168.0.1/done", false),
+            ("http://100.64.0.1/done", false),
+            ("http://169.254.169.254/latest/meta-data", false),
+            ("http://[::1]/done", false),
+            ("http://[::ffff:127.0.0.1]/done", false),
+            ("http://[fd00::1]/done", false),
+            ("http://[fe80::1]/done", false),
+        ];
+
+        test_support::run(async {
+            for (url, expected) in cases {
+                let checked = callback_url(Some(url)).await;
+                assert_eq!(checked.is_ok(), expected, "{}: {:?}", url, checked);
+            }
+        })
+    }
+
+    #[test]
+    fn new_payment_batch_reports_bad_callback_urls_by_index() {
+        test_support::run(async {
+            let mut private = posted("1");
+            private["callbackUrl"] = json!("http://127.0.0.1:9999/done");
+            let response = test_support::send(
+                post(
+                    "/payments/batch",
+                    json!([posted("0"), private, posted("2")]),
+                ),
+                "10.0.6.3:4000".parse().unwrap(),
+            )
+            .await;
+
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body: serde_json::Value =
+                serde_json::from_slice(&test_support::body(response).await).unwrap();
+            assert_eq!(
+                body["violations"],
+                json!(["[0] amount must be positive", "[1] invalid callback_url"])
+            );
+        })
+    }
+}
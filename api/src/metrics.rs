@@ -0,0 +1,154 @@
+use axum::{http::header::CONTENT_TYPE, response::IntoResponse};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::time::Duration;
+
+use crate::{db, error::ApiError, in_flight, metrics, repository, sender};
+
+#[derive(Debug)]
+pub struct Metrics {
+    pub registry: Registry,
+    pub payments_log_size_bytes: IntGauge,
+    pub payments_log_row_count: IntGauge,
+    pub payments_received_total: IntCounter,
+    pub payments_processed_total: IntCounterVec,
+    pub payments_failed_total: IntCounter,
+    pub payments_in_flight: IntGauge,
+    pub queue_depth: IntGauge,
+    pub processor_request_duration_seconds: HistogramVec,
+    pub db_insert_duration_seconds: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let payments_log_size_bytes = IntGauge::new(
+            "payments_log_size_bytes",
+            "total size of payments.log on disk, including indexes and toast",
+        )
+        .unwrap();
+        let payments_log_row_count = IntGauge::new(
+            "payments_log_row_count",
+            "estimated number of rows in payments.log, as of the last analyze",
+        )
+        .unwrap();
+        let payments_received_total = IntCounter::new(
+            "payments_received_total",
+            "payments accepted into the queue",
+        )
+        .unwrap();
+        let payments_processed_total = IntCounterVec::new(
+            Opts::new(
+                "payments_processed_total",
+                "payments accepted by each external processor",
+            ),
+            &["processor"],
+        )
+        .unwrap();
+        let payments_failed_total = IntCounter::new(
+            "payments_failed_total",
+            "payments given up on, either past their deadline or out of retries",
+        )
+        .unwrap();
+        let payments_in_flight = IntGauge::new(
+            "payments_in_flight",
+            "payments taken off the queue and not yet done, as of the scrape",
+        )
+        .unwrap();
+        let queue_depth = IntGauge::new(
+            "queue_depth",
+            "payments waiting in the queue, as of the scrape",
+        )
+        .unwrap();
+        let processor_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "processor_request_duration_seconds",
+                "duration of each request to an external processor, failed ones included",
+            ),
+            &["processor"],
+        )
+        .unwrap();
+        let db_insert_duration_seconds = Histogram::with_opts(HistogramOpts::new(
+            "db_insert_duration_seconds",
+            "duration of each insert into payments.log",
+        ))
+        .unwrap();
+
+        registry
+            .register(Box::new(payments_log_size_bytes.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(payments_log_row_count.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(payments_received_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(payments_processed_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(payments_failed_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(payments_in_flight.clone()))
+            .unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry
+            .register(Box::new(processor_request_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_insert_duration_seconds.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            payments_log_size_bytes,
+            payments_log_row_count,
+            payments_received_total,
+            payments_processed_total,
+            payments_failed_total,
+            payments_in_flight,
+            queue_depth,
+            processor_request_duration_seconds,
+            db_insert_duration_seconds,
+        }
+    }
+}
+
+pub async fn scrape() -> impl IntoResponse {
+    // both are read off the live structures rather than tracked at every change
+    metrics().payments_in_flight.set(in_flight() as i64);
+    metrics().queue_depth.set(sender().len() as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+
+    match encoder.encode(&metrics().registry.gather(), &mut buffer) {
+        Ok(_) => Ok(([(CONTENT_TYPE, encoder.format_type().to_string())], buffer)),
+        Err(error) => {
+            tracing::error!("failed encoding metrics, {}", error);
+            Err(ApiError::InternalError(
+                "failed encoding metrics".to_string(),
+            ))
+        }
+    }
+}
+
+pub async fn refresh_table_size_forever(interval: Duration) {
+    loop {
+        match repository::log_table_size(db()).await {
+            Ok(size) => {
+                metrics().payments_log_size_bytes.set(size.size_bytes);
+                metrics().payments_log_row_count.set(size.row_count);
+            }
+            Err(error) => {
+                tracing::error!("failed fetching payments.log size, {}", error);
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
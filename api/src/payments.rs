@@ -0,0 +1,372 @@
+use axum::{
+    Json,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use flume::TrySendError;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    admin, db,
+    error::ApiError,
+    processor::Payment,
+    queue_full,
+    repository::{self, ListFilter},
+    sender,
+};
+
+const INCLUDE_ATTEMPTS_HEADER: &str = "x-include-attempts";
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 500;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Completed,
+}
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    processor: Option<String>,
+    status: Option<Status>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+pub async fn list_payments(Query(params): Query<ListParams>) -> Response {
+    let limit = params.limit.unwrap_or(DEFAULT_LIST_LIMIT);
+    if !(1..=MAX_LIST_LIMIT).contains(&limit) {
+        return ApiError::InvalidInput("limit out of range".to_string()).into_response();
+    }
+
+    let after = match params.cursor.as_deref().map(decode_cursor) {
+        Some(Some(after)) => Some(after),
+        Some(None) => return ApiError::InvalidInput("invalid cursor".to_string()).into_response(),
+        None => None,
+    };
+
+    let filter = ListFilter {
+        from: params.from,
+        to: params.to,
+        processor: params.processor,
+        completed: params
+            .status
+            .map(|status| matches!(status, Status::Completed)),
+    };
+
+    // one row past the page tells whether there is a next one
+    match repository::list(db(), &filter, after, limit + 1).await {
+        Ok(mut payments) => {
+            let next_cursor = if payments.len() as i64 > limit {
+                payments.truncate(limit as usize);
+                payments
+                    .last()
+                    .map(|last| encode_cursor(last.requested_at, last.id))
+            } else {
+                None
+            };
+
+            let payments: Vec<_> = payments.iter().map(|payment| payment.to_json()).collect();
+            (
+                StatusCode::OK,
+                Json(json!({ "payments": payments, "nextCursor": next_cursor })),
+            )
+                .into_response()
+        }
+        Err(error) => {
+            tracing::error!("failed listing payments, {}", error);
+            ApiError::DatabaseUnavailable.into_response()
+        }
+    }
+}
+
+/// opaque to clients, the position of the last payment they've seen, also used by the summary's
+/// `since_cursor` keyed on processed_at instead
+pub fn encode_cursor(at: DateTime<Utc>, id: Uuid) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{},{}", at.to_rfc3339(), id))
+}
+
+pub fn decode_cursor(cursor: &str) -> Option<(DateTime<Utc>, Uuid)> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    let (at, id) = decoded.split_once(',')?;
+
+    Some((
+        DateTime::parse_from_rfc3339(at).ok()?.to_utc(),
+        Uuid::parse_str(id).ok()?,
+    ))
+}
+
+/// an id that isn't a uuid is rejected by the path extractor with a 400
+pub async fn get_payment(Path(id): Path<Uuid>, headers: HeaderMap) -> Result<Response, ApiError> {
+    let include_attempts = headers
+        .get(INCLUDE_ATTEMPTS_HEADER)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"true"));
+
+    let result = if include_attempts {
+        repository::get_payment_with_attempts(db(), id)
+            .await
+            .map(|found| {
+                found.map(|(payment, attempts)| json!({ "payment": payment.to_json(), "attempts": attempts }))
+            })
+    } else {
+        repository::get_payment(db(), id)
+            .await
+            .map(|found| found.map(|payment| payment.to_json()))
+    };
+
+    match result {
+        Ok(Some(payment)) => Ok((StatusCode::OK, Json(payment)).into_response()),
+        Ok(None) => Err(ApiError::NotFound("payment not found".to_string())),
+        Err(error) => {
+            tracing::error!("failed fetching payment {}, {}", id, error);
+            Err(ApiError::InternalError(
+                "failed fetching payment".to_string(),
+            ))
+        }
+    }
+}
+
+/// every event recorded for the payment, oldest first
+pub async fn audit_log(Path(id): Path<Uuid>) -> Result<Response, ApiError> {
+    match repository::audit_log(db(), id).await {
+        Ok(events) if events.is_empty() => Err(ApiError::NotFound("payment not found".to_string())),
+        Ok(events) => Ok((StatusCode::OK, Json(events)).into_response()),
+        Err(error) => {
+            tracing::error!("failed fetching audit log of {}, {}", id, error);
+            Err(ApiError::InternalError(
+                "failed fetching audit log".to_string(),
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeadLetterParams {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+pub async fn dead_letters(
+    Query(params): Query<DeadLetterParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    if !admin::authorized(&headers) {
+        return Err(ApiError::Unauthorized);
+    }
+
+    match repository::dead_letters(db(), params.from, params.to).await {
+        Ok(dead_letters) => Ok((StatusCode::OK, Json(dead_letters)).into_response()),
+        Err(error) => {
+            tracing::error!("failed fetching dead letters, {}", error);
+            Err(ApiError::InternalError(
+                "failed fetching dead letters".to_string(),
+            ))
+        }
+    }
+}
+
+/// the dead letter is only removed once the payment made it back into the channel
+pub async fn requeue(Path(id): Path<Uuid>, headers: HeaderMap) -> Result<StatusCode, ApiError> {
+    if !admin::authorized(&headers) {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let failed = |error: sqlx::Error| {
+        tracing::error!("failed requeueing {}, {}", id, error);
+        ApiError::InternalError("failed requeueing payment".to_string())
+    };
+
+    let mut transaction = db().begin().await.map_err(failed)?;
+    let dead_letter = repository::take_dead_letter(&mut transaction, id)
+        .await
+        .map_err(failed)?
+        .ok_or_else(|| ApiError::NotFound("dead letter not found".to_string()))?;
+
+    let payment = Payment {
+        correlation_id: dead_letter.id,
+        amount: dead_letter.amount,
+        requested_at: dead_letter.requested_at,
+        source_ip: None,
+        timeout_secs: None,
+        requeued: true,
+        callback_url: None,
+    };
+
+    match sender().try_send(payment) {
+        Ok(_) => {
+            transaction.commit().await.map_err(failed)?;
+            tracing::info!("{} requeued", id);
+            Ok(StatusCode::ACCEPTED)
+        }
+        // dropping the transaction rolls it back, so the dead letter stays for the next try
+        Err(TrySendError::Full(_)) => Err(queue_full()),
+        Err(error) => {
+            tracing::error!("failed requeueing {}, {}", id, error);
+            Err(ApiError::InternalError(
+                "failed requeueing payment".to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            Request,
+            header::{AUTHORIZATION, RETRY_AFTER},
+        },
+    };
+    use chrono::Utc;
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::test_support::{self, ADMIN_TOKEN, fill_queue, processed_payment, resume};
+
+    async fn get(uri: String) -> (StatusCode, serde_json::Value) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = test_support::send(request, "10.0.4.1:4000".parse().unwrap()).await;
+        let status = response.status();
+        let body = test_support::body(response).await;
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[test]
+    fn get_payment_finds_a_pending_payment() {
+        test_support::run(async {
+            let id = Uuid::new_v4();
+            repository::insert(db(), id, Decimal::new(1990, 2), Utc::now(), None, None)
+                .await
+                .unwrap();
+
+            let (status, payment) = get(format!("/payments/{}", id)).await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(payment["correlationId"], id.to_string());
+            assert_eq!(payment["status"], "pending");
+            assert!(payment["processedBy"].is_null());
+        })
+    }
+
+    #[test]
+    fn get_payment_reports_who_processed_it() {
+        test_support::run(async {
+            let id = processed_payment("fallback", Decimal::TEN, Utc::now()).await;
+
+            let (status, payment) = get(format!("/payments/{}", id)).await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(payment["status"], "completed");
+            assert_eq!(payment["processedBy"], "fallback");
+        })
+    }
+
+    #[test]
+    fn get_payment_of_an_unknown_id_is_not_found() {
+        test_support::run(async {
+            let (status, body) = get(format!("/payments/{}", Uuid::new_v4())).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body["code"], "not_found");
+        })
+    }
+
+    #[test]
+    fn get_payment_of_anything_but_a_uuid_is_a_bad_request() {
+        test_support::run(async {
+            let (status, _) = get("/payments/not-a-uuid".to_string()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        })
+    }
+
+    fn requeue_request(id: Uuid, token: Option<&str>) -> Request<Body> {
+        let mut request = Request::post(format!("/payments/{}/requeue", id));
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    async fn dead_letter() -> Uuid {
+        let id = Uuid::new_v4();
+        let requested_at = Utc::now();
+        repository::insert(db(), id, Decimal::TEN, requested_at, None, None)
+            .await
+            .unwrap();
+        repository::dead_letter(db(), id, Decimal::TEN, requested_at, "500", 4)
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn dead_lettered(id: Uuid) -> bool {
+        repository::dead_letters(db(), None, None)
+            .await
+            .unwrap()
+            .iter()
+            .any(|dead_letter| dead_letter.id == id)
+    }
+
+    #[test]
+    fn requeue_into_a_full_queue_keeps_the_dead_letter_for_later() {
+        test_support::run(async {
+            let id = dead_letter().await;
+            let peer = "10.0.4.2:4000".parse().unwrap();
+
+            fill_queue(0).await;
+            let response = test_support::send(requeue_request(id, Some(ADMIN_TOKEN)), peer).await;
+            resume().await;
+
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert!(response.headers().contains_key(RETRY_AFTER));
+            let body: serde_json::Value =
+                serde_json::from_slice(&test_support::body(response).await).unwrap();
+            assert_eq!(body["code"], "queue_full");
+            assert!(dead_lettered(id).await);
+
+            let response = test_support::send(requeue_request(id, Some(ADMIN_TOKEN)), peer).await;
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+            assert!(!dead_lettered(id).await);
+        })
+    }
+
+    #[test]
+    fn requeue_errors_come_back_with_a_code() {
+        test_support::run(async {
+            let peer = "10.0.4.3:4000".parse().unwrap();
+            let cases = [
+                (
+                    requeue_request(dead_letter().await, None),
+                    StatusCode::UNAUTHORIZED,
+                    "unauthorized",
+                ),
+                (
+                    requeue_request(dead_letter().await, Some("wrong")),
+                    StatusCode::UNAUTHORIZED,
+                    "unauthorized",
+                ),
+                (
+                    requeue_request(Uuid::new_v4(), Some(ADMIN_TOKEN)),
+                    StatusCode::NOT_FOUND,
+                    "not_found",
+                ),
+            ];
+
+            for (request, status, code) in cases {
+                let response = test_support::send(request, peer).await;
+                assert_eq!(response.status(), status);
+                let body: serde_json::Value =
+                    serde_json::from_slice(&test_support::body(response).await).unwrap();
+                assert_eq!(body["code"], code);
+            }
+        })
+    }
+}
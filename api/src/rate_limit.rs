@@ -0,0 +1,136 @@
+use axum::{
+    extract::{ConnectInfo, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter, clock::Clock};
+use std::{
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    time::Duration,
+};
+
+use crate::{client_ip, error::ApiError, rate_limiter};
+
+/// `None` when `rate_per_second` is 0, burst defaults to one second's worth
+pub fn new(rate_per_second: u32, burst_size: u32) -> Option<DefaultKeyedRateLimiter<IpAddr>> {
+    let rate = NonZeroU32::new(rate_per_second)?;
+    let burst = NonZeroU32::new(burst_size).unwrap_or(rate);
+
+    Some(RateLimiter::dashmap(
+        Quota::per_second(rate).allow_burst(burst),
+    ))
+}
+
+/// token bucket per client ip, a client past its burst waits until its next token
+pub async fn limit(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(limiter) = rate_limiter() else {
+        return next.run(request).await;
+    };
+
+    let ip = client_ip(request.headers(), peer);
+    match limiter.check_key(&ip) {
+        Ok(_) => next.run(request).await,
+        Err(not_until) => {
+            let wait = not_until.wait_time_from(limiter.clock().now());
+            tracing::debug!("{} rate limited for {:?}", ip, wait);
+            ApiError::RateLimited {
+                retry_after_secs: wait.as_secs_f64().ceil().max(1.0) as u64,
+            }
+            .into_response()
+        }
+    }
+}
+
+/// buckets that have filled back up are indistinguishable from new ones, so they can go
+pub async fn prune_forever(interval: Duration) {
+    let Some(limiter) = rate_limiter() else {
+        return;
+    };
+
+    loop {
+        tokio::time::sleep(interval).await;
+        limiter.retain_recent();
+        limiter.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header::RETRY_AFTER},
+    };
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use uuid::Uuid;
+
+    use crate::test_support::{self, BURST_SIZE};
+
+    fn payment(forwarded_for: Option<&str>) -> Request<Body> {
+        let mut request = Request::post("/payments").header("content-type", "application/json");
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.header("x-forwarded-for", forwarded_for);
+        }
+
+        let body = json!({ "correlationId": Uuid::new_v4(), "amount": 10 });
+        request.body(Body::from(body.to_string())).unwrap()
+    }
+
+    #[test]
+    fn posts_past_the_burst_are_turned_away_until_the_next_token() {
+        test_support::run(async {
+            let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+            for _ in 0..BURST_SIZE {
+                let response = test_support::send(payment(None), peer).await;
+                assert_eq!(response.status(), StatusCode::CREATED);
+            }
+
+            let response = test_support::send(payment(None), peer).await;
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            let retry_after: u64 = response.headers()[RETRY_AFTER]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(retry_after, 1);
+            assert!(test_support::text(response).await.contains("rate_limited"));
+
+            let other: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+            let response = test_support::send(payment(None), other).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+        })
+    }
+
+    #[test]
+    fn spoofed_forwarded_for_entries_share_the_bucket_of_the_address_nginx_saw() {
+        test_support::run(async {
+            let nginx: SocketAddr = "172.16.0.1:4000".parse().unwrap();
+            let statuses: Vec<StatusCode> = spoofed_statuses(nginx).await;
+
+            assert_eq!(
+                statuses
+                    .iter()
+                    .filter(|status| **status == StatusCode::CREATED)
+                    .count(),
+                BURST_SIZE as usize
+            );
+            assert!(statuses.contains(&StatusCode::TOO_MANY_REQUESTS));
+        })
+    }
+
+    /// every request claims to come from somewhere else, nginx appends the same real address
+    async fn spoofed_statuses(nginx: SocketAddr) -> Vec<StatusCode> {
+        let mut statuses = Vec::new();
+        for spoofed in 1..=BURST_SIZE + 2 {
+            let forwarded_for = format!("203.0.113.{}, 10.0.0.3", spoofed);
+            let response = test_support::send(payment(Some(&forwarded_for)), nginx).await;
+            statuses.push(response.status());
+        }
+        statuses
+    }
+}
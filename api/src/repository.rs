@@ -1,21 +1,350 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
 use sqlx::{
-    Pool, Postgres,
+    FromRow, PgConnection, Pool, Postgres, QueryBuilder,
     types::{Decimal, JsonValue},
 };
+use std::net::IpAddr;
 use uuid::Uuid;
 
+use crate::{metrics, processor::Payment};
+
+#[derive(Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentRecord {
+    #[serde(rename = "correlationId")]
+    pub id: Uuid,
+    #[serde(with = "rust_decimal::serde::arbitrary_precision")]
+    pub amount: Decimal,
+    pub requested_at: DateTime<Utc>,
+    pub processed_by: Option<String>,
+}
+
+impl PaymentRecord {
+    /// derived from processed_by, a payment is only ever in one of the two
+    pub fn status(&self) -> &'static str {
+        match self.processed_by {
+            Some(_) => "completed",
+            None => "pending",
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut value = serde_json::json!(self);
+        value["status"] = self.status().into();
+        value
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    #[serde(rename = "correlationId")]
+    pub id: Uuid,
+    #[serde(with = "rust_decimal::serde::arbitrary_precision")]
+    pub amount: Decimal,
+    pub requested_at: DateTime<Utc>,
+    pub failed_at: DateTime<Utc>,
+    pub last_error: String,
+    pub attempts: i32,
+}
+
+#[derive(Serialize)]
+pub struct ConfigEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(rename = "at")]
+    pub occurred_at: DateTime<Utc>,
+    pub detail: JsonValue,
+}
+
+/// one step in a payment's history
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub event: String,
+    pub detail: JsonValue,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BucketSize {
+    Minute,
+    Hour,
+    SixHours,
+    Day,
+}
+
+impl BucketSize {
+    pub fn seconds(self) -> f64 {
+        match self {
+            BucketSize::Minute => 60.0,
+            BucketSize::Hour => 3_600.0,
+            BucketSize::SixHours => 21_600.0,
+            BucketSize::Day => 86_400.0,
+        }
+    }
+
+    /// the `date_trunc` field for the bucket, if it lines up with one
+    pub fn calendar_unit(self) -> Option<&'static str> {
+        match self {
+            BucketSize::Minute => Some("minute"),
+            BucketSize::Hour => Some("hour"),
+            BucketSize::SixHours => None,
+            BucketSize::Day => Some("day"),
+        }
+    }
+}
+
+/// narrows down [`list`], every field is optional
+pub struct ListFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub processor: Option<String>,
+    /// `Some(false)` for pending payments only
+    pub completed: Option<bool>,
+}
+
+/// narrows down every summary query, see [`filtered_log`]
+pub struct SummaryFilter {
+    pub processor_names: Vec<String>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub metadata: Option<JsonValue>,
+    /// where a polling client left off, only narrows down the per processor totals
+    pub since: Option<Since>,
+    /// restricts the summary to these correlation ids
+    pub ids: Option<Vec<Uuid>>,
+}
+
+/// exclusive lower bound for polling clients
+#[derive(Clone, Copy)]
+pub enum Since {
+    /// on requested_at, misses payments processed after a newer one was already seen
+    RequestedAfter(DateTime<Utc>),
+    /// on `(processed_at, id)`, which only ever grows as payments get processed
+    ProcessedAfter(DateTime<Utc>, Uuid),
+}
+
+#[derive(FromRow)]
+pub struct SummaryBucket {
+    pub t: DateTime<Utc>,
+    pub processed_by: String,
+    pub total_amount: Decimal,
+    pub total_requests: i64,
+}
+
+#[derive(FromRow)]
+pub struct SummaryRollup {
+    pub total_amount: Option<Decimal>,
+    pub total_requests: i64,
+}
+
+pub struct TableSize {
+    pub size_bytes: i64,
+    pub row_count: i64,
+}
+
+#[derive(FromRow)]
+pub struct SloCompliance {
+    pub name: String,
+    pub compliance: Option<f64>,
+}
+
+#[derive(FromRow)]
+pub struct CancelledSummary {
+    pub total_amount: Option<Decimal>,
+    pub total_requests: i64,
+    pub by_user: i64,
+    pub by_system: i64,
+}
+
+#[derive(FromRow)]
+pub struct SourceIpCount {
+    pub ip: IpAddr,
+    pub count: i64,
+}
+
+#[derive(FromRow)]
+pub struct HistogramCount {
+    pub name: String,
+    pub bucket: i32,
+    pub count: i64,
+}
+
+#[derive(FromRow)]
+pub struct FailedAttempts {
+    pub name: String,
+    pub count: i64,
+    pub last_error: Option<String>,
+}
+
+#[derive(FromRow)]
+pub struct NetworkStats {
+    pub name: String,
+    pub total_http_requests: i64,
+    pub total_retries: i64,
+    pub avg_retry_count: Option<f64>,
+}
+
+#[derive(FromRow)]
+pub struct AmountStats {
+    pub name: String,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+    pub mean: Option<Decimal>,
+    pub median: Option<Decimal>,
+    pub stddev: Option<Decimal>,
+}
+
+#[derive(FromRow)]
+pub struct DailySummary {
+    pub name: String,
+    pub date: NaiveDate,
+    pub total_amount: Decimal,
+    pub total_requests: i64,
+}
+
+#[derive(FromRow)]
+pub struct Velocity {
+    pub name: String,
+    pub per_minute: f64,
+}
+
+#[derive(FromRow)]
+pub struct TimeToFirstPayment {
+    pub name: String,
+    pub seconds: Option<f64>,
+}
+
+#[derive(FromRow)]
+pub struct Burstiness {
+    pub name: String,
+    pub coefficient: Option<f64>,
+}
+
+#[derive(FromRow)]
+pub struct HighToLowRatio {
+    pub name: String,
+    pub ratio: Option<f64>,
+}
+
+#[derive(FromRow)]
+pub struct SlaBreaches {
+    pub name: String,
+    pub count: i64,
+    pub total: i64,
+    pub ids: Vec<Uuid>,
+}
+
+#[derive(FromRow)]
+pub struct RetriedPayments {
+    pub name: String,
+    pub count: i64,
+    pub total: i64,
+}
+
+#[derive(FromRow)]
+pub struct DayOfWeekTotals {
+    /// 0 is sunday
+    pub day_of_week: i32,
+    pub total_amount: Decimal,
+    pub total_requests: i64,
+}
+
+#[derive(FromRow)]
+pub struct HourOfDayCount {
+    pub hour: i32,
+    pub total_requests: i64,
+}
+
+#[derive(FromRow)]
+pub struct ProcessingInterval {
+    pub name: String,
+    pub requested_at: DateTime<Utc>,
+    pub processed_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+pub struct IdlePeriod {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub duration_minutes: i64,
+}
+
+#[derive(FromRow)]
+pub struct FailureModeCount {
+    pub name: String,
+    pub mode: String,
+    pub count: i64,
+}
+
+#[derive(FromRow)]
+pub struct ProcessingEfficiency {
+    pub name: String,
+    pub success_rate: f64,
+    pub avg_attempts_per_payment: f64,
+}
+
+#[derive(FromRow)]
+pub struct UuidVersions {
+    pub name: String,
+    pub v4: i64,
+    pub v7: i64,
+    pub other: i64,
+}
+
+#[derive(FromRow)]
+pub struct AmountQuartiles {
+    pub name: String,
+    pub q1: Option<Decimal>,
+    pub q2: Option<Decimal>,
+    pub q3: Option<Decimal>,
+}
+
+#[derive(FromRow)]
+pub struct PaymentIds {
+    pub name: String,
+    pub ids: Vec<Uuid>,
+}
+
+#[derive(FromRow)]
+pub struct SourceIpTotals {
+    pub ip: IpAddr,
+    pub total_amount: Decimal,
+    pub total_requests: i64,
+}
+
+#[derive(FromRow)]
+pub struct ProcessorLatency {
+    pub name: String,
+    pub latency_ms: Option<f64>,
+}
+
+/// cheapest round trip there is, proves a connection can be checked out and used
+pub async fn ping(db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query!("select 1 as one").fetch_one(db).await?;
+
+    Ok(())
+}
+
 pub async fn insert(
     db: &Pool<Postgres>,
     id: Uuid,
     amount: Decimal,
     requested_at: DateTime<Utc>,
+    source_ip: Option<IpAddr>,
+    callback_url: Option<&str>,
 ) -> Result<(), sqlx::Error> {
+    let _timer = metrics().db_insert_duration_seconds.start_timer();
+
     sqlx::query!(
-        "insert into payments.log (id, amount, requested_at) values($1, $2, $3)",
+        "insert into payments.log (id, amount, requested_at, source_ip, callback_url) values($1, $2, $3, $4, $5)",
         id,
         amount,
-        requested_at
+        requested_at,
+        source_ip as _,
+        callback_url
     )
     .execute(db)
     .await?;
@@ -23,13 +352,37 @@ pub async fn insert(
     Ok(())
 }
 
+/// ids already in the log are skipped rather than failing the whole batch, the ids that were
+/// actually inserted come back
+pub async fn insert_batch(
+    db: &Pool<Postgres>,
+    payments: &[Payment],
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let _timer = metrics().db_insert_duration_seconds.start_timer();
+
+    let mut query = QueryBuilder::new(
+        "insert into payments.log (id, amount, requested_at, source_ip, callback_url) ",
+    );
+    query
+        .push_values(payments, |mut row, payment| {
+            row.push_bind(payment.correlation_id)
+                .push_bind(payment.amount)
+                .push_bind(payment.requested_at)
+                .push_bind(payment.source_ip)
+                .push_bind(payment.callback_url.as_deref());
+        })
+        .push(" on conflict (id) do nothing returning id");
+
+    query.build_query_scalar().fetch_all(db).await
+}
+
 pub async fn set_processed_by(
     db: &Pool<Postgres>,
     id: Uuid,
     processed_by: &str,
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        "update payments.log set processed_by = $2 where id = $1",
+        "update payments.log set processed_by = $2, processed_at = now() where id = $1",
         id,
         processed_by
     )
@@ -39,49 +392,1550 @@ pub async fn set_processed_by(
     Ok(())
 }
 
-pub async fn summary(
+/// cancels on behalf of the system, e.g. when a payment misses its deadline
+pub async fn cancel(db: &Pool<Postgres>, id: Uuid, reason: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "update payments.log set cancelled_at = now(), cancelled_by = 'system', cancel_reason = $2 where id = $1",
+        id,
+        reason
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// parks the payment and cancels it in the log, so it stops counting as pending
+pub async fn dead_letter(
     db: &Pool<Postgres>,
-    processor_names: &Vec<String>,
-    from: DateTime<Utc>,
-    to: DateTime<Utc>,
-) -> Result<JsonValue, sqlx::Error> {
+    id: Uuid,
+    amount: Decimal,
+    requested_at: DateTime<Utc>,
+    last_error: &str,
+    attempts: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"with dead_letter as (
+	insert into payments.dead_letter (id, amount, requested_at, last_error, attempts)
+	values ($1, $2, $3, $4, $5)
+	on conflict (id) do update set
+		failed_at = now(),
+		last_error = excluded.last_error,
+		attempts = excluded.attempts
+)
+update payments.log set cancelled_at = now(), cancelled_by = 'system', cancel_reason = 'retries_exhausted' where id = $1"#,
+        id,
+        amount,
+        requested_at,
+        last_error,
+        attempts
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn dead_letters(
+    db: &Pool<Postgres>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Vec<DeadLetter>, sqlx::Error> {
+    sqlx::query_as!(
+        DeadLetter,
+        r#"select
+	id,
+	amount,
+	requested_at,
+	failed_at,
+	last_error,
+	attempts
+from
+	payments.dead_letter
+where
+	($1::timestamptz is null or requested_at >= $1)
+	and ($2::timestamptz is null or requested_at < $2)
+order by
+	failed_at"#,
+        from,
+        to
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// takes the payment out of the dead letters and lifts its cancellation, meant to run in a
+/// transaction that only commits once the payment is back in the channel
+pub async fn take_dead_letter(
+    db: &mut PgConnection,
+    id: Uuid,
+) -> Result<Option<DeadLetter>, sqlx::Error> {
+    sqlx::query_as!(
+        DeadLetter,
+        r#"with dead_letter as (
+	delete from payments.dead_letter where id = $1
+	returning id, amount, requested_at, failed_at, last_error, attempts
+), log as (
+	update payments.log set cancelled_at = null, cancelled_by = null, cancel_reason = null
+	where id in (select id from dead_letter)
+)
+select
+	id as "id!",
+	amount as "amount!",
+	requested_at as "requested_at!",
+	failed_at as "failed_at!",
+	last_error as "last_error!",
+	attempts as "attempts!"
+from
+	dead_letter"#,
+        id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn record_config_event(
+    db: &Pool<Postgres>,
+    event_type: &str,
+    detail: &JsonValue,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "insert into payments.config_events (event_type, detail) values ($1, $2)",
+        event_type,
+        detail
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// detail of the most recent event of the given type
+pub async fn last_config_event(
+    db: &Pool<Postgres>,
+    event_type: &str,
+) -> Result<Option<JsonValue>, sqlx::Error> {
     sqlx::query_scalar!(
-        r#"with processors as (
+        "select detail from payments.config_events where event_type = $1 order by occurred_at desc limit 1",
+        event_type
+    )
+    .fetch_optional(db)
+    .await
+}
+
+pub async fn config_events(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<ConfigEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        ConfigEvent,
+        r#"select
+	event_type,
+	occurred_at,
+	detail
+from
+	payments.config_events
+where
+	occurred_at >= $1
+	and occurred_at < $2
+order by
+	occurred_at"#,
+        filter.from,
+        filter.to
+    )
+    .fetch_all(db)
+    .await
+}
+
+pub async fn audit(
+    db: &Pool<Postgres>,
+    payment_id: Uuid,
+    event: &str,
+    detail: &JsonValue,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "insert into payments.audit_log (payment_id, event, detail) values ($1, $2, $3)",
+        payment_id,
+        event,
+        detail
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// same event with no detail for every payment, for the ones a batch insert let through
+pub async fn audit_batch(
+    db: &Pool<Postgres>,
+    payment_ids: &[Uuid],
+    event: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "insert into payments.audit_log (payment_id, event) select unnest($1::uuid[]), $2",
+        payment_ids,
+        event
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// the id breaks ties between events recorded within the same instant
+pub async fn audit_log(
+    db: &Pool<Postgres>,
+    payment_id: Uuid,
+) -> Result<Vec<AuditEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        AuditEvent,
+        r#"select
+	event,
+	detail,
+	occurred_at
+from
+	payments.audit_log
+where
+	payment_id = $1
+order by
+	occurred_at,
+	id"#,
+        payment_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+pub async fn log_duplicate(
+    db: &Pool<Postgres>,
+    correlation_id: Uuid,
+    received_at: DateTime<Utc>,
+    source_ip: Option<IpAddr>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "insert into payments.duplicate_log (correlation_id, received_at, source_ip) values($1, $2, $3)",
+        correlation_id,
+        received_at,
+        source_ip as _
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// `error` is `None` for the attempt that succeeded
+pub async fn record_attempt(
+    db: &Pool<Postgres>,
+    payment_id: Uuid,
+    processor: &str,
+    attempt: i32,
+    error: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "insert into payments.processor_attempts (payment_id, processor, attempt, succeeded, error) values($1, $2, $3, $4, $5)",
+        payment_id,
+        processor,
+        attempt,
+        error.is_none(),
+        error
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_payment(
+    db: &Pool<Postgres>,
+    id: Uuid,
+) -> Result<Option<PaymentRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        PaymentRecord,
+        "select id, amount, requested_at, processed_by from payments.log where id = $1",
+        id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// newest first, keyset paginated on `(requested_at, id)`, `after` being the last row of the
+/// previous page
+pub async fn list(
+    db: &Pool<Postgres>,
+    filter: &ListFilter,
+    after: Option<(DateTime<Utc>, Uuid)>,
+    limit: i64,
+) -> Result<Vec<PaymentRecord>, sqlx::Error> {
+    let (after_requested_at, after_id) = after.unzip();
+    sqlx::query_as!(
+        PaymentRecord,
+        r#"select
+	id,
+	amount,
+	requested_at,
+	processed_by
+from
+	payments.log
+where
+	($1::timestamptz is null or requested_at >= $1)
+	and ($2::timestamptz is null or requested_at < $2)
+	and ($3::text is null or processed_by = $3)
+	and ($4::bool is null or (processed_by is not null) = $4)
+	and ($5::timestamptz is null or (requested_at, id) < ($5, $6))
+order by
+	requested_at desc,
+	id desc
+limit $7"#,
+        filter.from,
+        filter.to,
+        filter.processor,
+        filter.completed,
+        after_requested_at,
+        after_id,
+        limit
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// the payment and its attempts in a single round trip
+pub async fn get_payment_with_attempts(
+    db: &Pool<Postgres>,
+    id: Uuid,
+) -> Result<Option<(PaymentRecord, JsonValue)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"select
+	l.id,
+	l.amount,
+	l.requested_at,
+	l.processed_by,
+	coalesce(
+		json_agg(
+			json_build_object(
+				'processor', a.processor,
+				'attempt', a.attempt,
+				'succeeded', a.succeeded,
+				'error', a.error,
+				'attemptedAt', a.attempted_at
+			)
+			order by
+				a.attempt
+		) filter (where a.id is not null),
+		'[]'
+	) as "attempts!: JsonValue"
+from
+	payments.log l
+	left join payments.processor_attempts a on a.payment_id = l.id
+where
+	l.id = $1
+group by
+	l.id;
+"#,
+        id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row.map(|row| {
+        let payment = PaymentRecord {
+            id: row.id,
+            amount: row.amount,
+            requested_at: row.requested_at,
+            processed_by: row.processed_by,
+        };
+        (payment, row.attempts)
+    }))
+}
+
+pub async fn last_n_window(
+    db: &Pool<Postgres>,
+    n: i64,
+) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>, sqlx::Error> {
+    let window = sqlx::query!(
+        r#"select
+	min(requested_at) as "from",
+	max(requested_at) as "to"
+from
+	(
+		select
+			requested_at
+		from
+			payments.log
+		order by
+			requested_at desc
+		limit $1
+	) last_n;
+"#,
+        n
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(window.from.zip(window.to))
+}
+
+/// the row count comes from planner statistics, cheap but only as fresh as the last analyze
+pub async fn log_table_size(db: &Pool<Postgres>) -> Result<TableSize, sqlx::Error> {
+    sqlx::query_as!(
+        TableSize,
+        r#"select
+	pg_total_relation_size(c.oid) as "size_bytes!",
+	greatest(c.reltuples, 0)::bigint as "row_count!"
+from
+	pg_class c
+where
+	c.oid = 'payments.log'::regclass;
+"#
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// starts a query with a `filtered_log` cte holding the rows of `payments.log` within the filter's
+/// window and criteria, so the summary queries select from it and stay consistent with each other.
+/// processor names are left to each query since some of them look at unprocessed payments too
+fn filtered_log(filter: &SummaryFilter, sample_rate: Option<f64>) -> QueryBuilder<'_, Postgres> {
+    push_filtered_log(QueryBuilder::new(""), filter, sample_rate)
+}
+
+fn push_filtered_log<'a>(
+    mut query: QueryBuilder<'a, Postgres>,
+    filter: &'a SummaryFilter,
+    sample_rate: Option<f64>,
+) -> QueryBuilder<'a, Postgres> {
+    query.push(
+        r#"with filtered_log as (
 	select
-		unnest as name
+		*
 	from
-		unnest($1::text[])
-),
-summaries as (
+		payments.log"#,
+    );
+
+    if let Some(rate) = sample_rate {
+        query
+            .push(" tablesample bernoulli (")
+            .push_bind(rate * 100.0)
+            .push("::real)");
+    }
+
+    query
+        .push("\n\twhere\n\t\trequested_at >= ")
+        .push_bind(filter.from)
+        .push("\n\t\tand requested_at < ")
+        .push_bind(filter.to);
+
+    if let Some(ids) = &filter.ids {
+        query.push("\n\t\tand id = any(").push_bind(ids).push(")");
+    }
+
+    if let Some(metadata) = &filter.metadata {
+        query
+            .push("\n\t\tand metadata @> ")
+            .push_bind(metadata)
+            .push("::jsonb");
+    }
+
+    query.push("\n)\n");
+    query
+}
+
+/// with a sample rate the totals come from a bernoulli sample of the log, scaled back up by the
+/// rate, trading accuracy for speed on large tables
+pub async fn summary(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    sample_rate: Option<f64>,
+    null_as_zero: bool,
+) -> Result<JsonValue, sqlx::Error> {
+    summary_query(QueryBuilder::new(""), filter, sample_rate, null_as_zero)
+        .build_query_scalar::<Option<JsonValue>>()
+        .fetch_one(db)
+        .await
+        .map(Option::unwrap_or_default)
+}
+
+/// the planner's estimate for [`summary`], without actually running it
+pub async fn summary_plan(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    sample_rate: Option<f64>,
+    null_as_zero: bool,
+) -> Result<JsonValue, sqlx::Error> {
+    let explain = QueryBuilder::new("explain (format json, costs true)\n");
+    summary_query(explain, filter, sample_rate, null_as_zero)
+        .build_query_scalar()
+        .fetch_one(db)
+        .await
+}
+
+fn summary_query<'a>(
+    query: QueryBuilder<'a, Postgres>,
+    filter: &'a SummaryFilter,
+    sample_rate: Option<f64>,
+    null_as_zero: bool,
+) -> QueryBuilder<'a, Postgres> {
+    let mut query = push_filtered_log(query, filter, sample_rate);
+    query
+        .push(
+            r#", processors as (
 	select
-		processed_by as name,
-		sum(amount) as total_amount,
-		count(amount) as total_requests
+		unnest as name
 	from
-		payments.log
-	where
-		processed_by = any($1)
-		and requested_at >= $2
-		and requested_at < $3
-	group by
+		unnest("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push("::text[])\n),\nsummaries as (\n\tselect\n\t\tprocessed_by as name,\n");
+
+    match sample_rate {
+        Some(rate) => query
+            .push("\t\tround(sum(amount) / ")
+            .push_bind(rate)
+            .push("::numeric, 2) as total_amount,\n\t\tround(count(amount) / ")
+            .push_bind(rate)
+            .push("::numeric) as total_requests\n"),
+        None => {
+            query.push("\t\tsum(amount) as total_amount,\n\t\tcount(amount) as total_requests\n")
+        }
+    };
+
+    query.push("\tfrom\n\t\tfiltered_log\n");
+    match filter.since {
+        Some(Since::RequestedAfter(requested_at)) => {
+            query
+                .push("\twhere\n\t\trequested_at > ")
+                .push_bind(requested_at)
+                .push("\n");
+        }
+        Some(Since::ProcessedAfter(processed_at, id)) => {
+            query
+                .push("\twhere\n\t\t(processed_at, id) > (")
+                .push_bind(processed_at)
+                .push(", ")
+                .push_bind(id)
+                .push(")\n");
+        }
+        None => {}
+    }
+
+    query.push(format!(
+        r#"	group by
 		processed_by
 )
 select
 	json_object_agg(
 		p.name,
 		json_build_object(
-			'totalAmount', coalesce(s.total_amount, 0),
-			'totalRequests', coalesce(s.total_requests, 0)
+			'totalAmount', {},
+			'totalRequests', {}
 		)
-	) as "summary!: JsonValue"
+	)
 from
 	processors p
 	left join summaries s on p.name = s.name;
 "#,
-        processor_names,
-        from,
-        to
+        or_zero("s.total_amount", null_as_zero),
+        or_zero("s.total_requests", null_as_zero),
+    ));
+
+    query
+}
+
+/// aggregates over no rows come out null, which is what clients opting out of zeros want to see
+fn or_zero(aggregate: &str, null_as_zero: bool) -> String {
+    if null_as_zero {
+        format!("coalesce({}, 0)", aggregate)
+    } else {
+        aggregate.to_string()
+    }
+}
+
+pub async fn summary_buckets(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    bucket_size: BucketSize,
+) -> Result<Vec<SummaryBucket>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push("select\n\tdate_bin(make_interval(secs => ")
+        .push_bind(bucket_size.seconds())
+        .push(
+            r#"), requested_at, timestamptz 'epoch') as t,
+	processed_by,
+	sum(amount) as total_amount,
+	count(amount) as total_requests
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\t1, 2\norder by\n\t1, 2;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// like [`summary_buckets`] but truncated to calendar units in utc, six hour buckets have no
+/// unit of their own and are binned from the epoch instead
+pub async fn summary_bucketed(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    bucket_size: BucketSize,
+) -> Result<Vec<SummaryBucket>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    match bucket_size.calendar_unit() {
+        Some(unit) => query
+            .push("select\n\tdate_trunc(")
+            .push_bind(unit)
+            .push(", requested_at, 'utc')"),
+        None => query
+            .push("select\n\tdate_bin(make_interval(secs => ")
+            .push_bind(bucket_size.seconds())
+            .push("), requested_at, timestamptz 'epoch')"),
+    };
+    query
+        .push(
+            r#" as t,
+	processed_by,
+	sum(amount) as total_amount,
+	count(amount) as total_requests
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\t1, 2\norder by\n\t1, 2;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// days are utc, whatever the session time zone is
+pub async fn daily_summary(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<DailySummary>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	processed_by as name,
+	date_trunc('day', requested_at at time zone 'utc')::date as date,
+	sum(amount) as total_amount,
+	count(amount) as total_requests
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tprocessed_by,\n\tdate\norder by\n\tdate;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+pub async fn summary_rollup(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    null_as_zero: bool,
+) -> Result<SummaryRollup, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(format!(
+            r#"select
+	{} as total_amount,
+	count(amount) as total_requests
+from
+	filtered_log
+where
+	processed_by = any("#,
+            or_zero("sum(amount)", null_as_zero)
+        ))
+        .push_bind(&filter.processor_names)
+        .push(");\n");
+
+    query.build_query_as().fetch_one(db).await
+}
+
+/// fraction of each processor's payments that were processed within its latency slo,
+/// `None` when the processor has no payments in the window
+pub async fn slo_compliance(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    processor_names: &[String],
+    slo_max_latency_ms: &[i64],
+) -> Result<Vec<SloCompliance>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	s.name,
+	(
+		count(l.id) filter (
+			where
+				extract(epoch from l.processed_at - l.requested_at) * 1000 <= s.slo_max_latency_ms
+		)
+	)::float8 / nullif(count(l.id), 0) as compliance
+from
+	unnest("#,
+        )
+        .push_bind(processor_names)
+        .push("::text[], ")
+        .push_bind(slo_max_latency_ms)
+        .push(
+            r#"::int8[]) as s(name, slo_max_latency_ms)
+	left join filtered_log l on l.processed_by = s.name
+	and l.processed_at is not null
+group by
+	s.name;
+"#,
+        );
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// cancelled payments aren't necessarily processed, so this ignores the processor names
+pub async fn cancelled_summary(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    null_as_zero: bool,
+) -> Result<CancelledSummary, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query.push(format!(
+        r#"select
+	{} as total_amount,
+	count(amount) as total_requests,
+	count(amount) filter (where cancelled_by = 'user') as by_user,
+	count(amount) filter (where cancelled_by = 'system') as by_system
+from
+	filtered_log
+where
+	cancelled_at is not null;
+"#,
+        or_zero("sum(amount)", null_as_zero)
+    ));
+
+    query.build_query_as().fetch_one(db).await
+}
+
+pub async fn average_latency(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<ProcessorLatency>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	processed_by as name,
+	avg(extract(epoch from processed_at - requested_at) * 1000)::float8 as latency_ms
+from
+	filtered_log
+where
+	processed_at is not null
+	and processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tprocessed_by;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// duplicates are dropped before reaching the log, so only the window applies to them
+pub async fn duplicate_count(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"select count(*) as "count!" from payments.duplicate_log where received_at >= $1 and received_at < $2"#,
+        filter.from,
+        filter.to
     )
     .fetch_one(db)
     .await
 }
+
+/// the earliest payment still waiting on a processor, or the latest one when none are. cancelled
+/// payments will never be processed, so they don't hold it back
+pub async fn processing_watermark(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query.push(
+        r#"select
+	coalesce(
+		min(requested_at) filter (where processed_by is null and cancelled_at is null),
+		max(requested_at)
+	)
+from
+	filtered_log;
+"#,
+    );
+
+    query.build_query_scalar().fetch_one(db).await
+}
+
+/// the most recent payments in the window, `shuffled` returns the same ones in no particular
+/// order
+pub async fn raw_payments(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    limit: i64,
+    shuffled: bool,
+) -> Result<Vec<PaymentRecord>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    if shuffled {
+        query.push(
+            "
+select
+	*
+from
+	(
+",
+        );
+    }
+    query
+        .push(
+            r#"select
+	id,
+	amount,
+	requested_at,
+	processed_by
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\norder by\n\trequested_at desc\nlimit ")
+        .push_bind(limit);
+    if shuffled {
+        query.push("\n\t) newest\norder by\n\trandom()");
+    }
+    query.push(";\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// how many of the filtered correlation ids exist in the window, processed or not
+pub async fn found_ids(db: &Pool<Postgres>, filter: &SummaryFilter) -> Result<i64, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query.push("select\n\tcount(distinct id)\nfrom\n\tfiltered_log;\n");
+
+    query.build_query_scalar().fetch_one(db).await
+}
+
+/// highest row version in the window, bumped by a trigger on every update to the log
+pub async fn max_row_version(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<i64, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query.push("select\n\tcoalesce(max(row_version), 0)\nfrom\n\tfiltered_log;\n");
+
+    query.build_query_scalar().fetch_one(db).await
+}
+
+/// every processed amount in the window, ascending
+pub async fn sorted_amounts(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<Decimal>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push("select\n\tamount\nfrom\n\tfiltered_log\nwhere\n\tprocessed_by = any(")
+        .push_bind(&filter.processor_names)
+        .push(")\norder by\n\tamount;\n");
+
+    query.build_query_scalar().fetch_all(db).await
+}
+
+/// from request to processing for every processed payment, payments logged before processed_at
+/// was tracked are left out
+pub async fn processing_intervals(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<ProcessingInterval>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	processed_by as name,
+	requested_at,
+	processed_at
+from
+	filtered_log
+where
+	processed_at is not null
+	and processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(");\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+pub async fn last_requested_at(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push("select\n\tmax(requested_at)\nfrom\n\tfiltered_log\nwhere\n\tprocessed_by = any(")
+        .push_bind(&filter.processor_names)
+        .push(");\n");
+
+    query.build_query_scalar().fetch_one(db).await
+}
+
+/// the keyset position of the last processed payment, where the next poll picks up from
+pub async fn last_processed(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Option<(DateTime<Utc>, Uuid)>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push("select\n\tprocessed_at,\n\tid\nfrom\n\tfiltered_log\nwhere\n\tprocessed_by = any(")
+        .push_bind(&filter.processor_names)
+        .push(")\n\tand processed_at is not null\norder by\n\tprocessed_at desc,\n\tid desc\nlimit 1;\n");
+
+    query.build_query_as().fetch_optional(db).await
+}
+
+pub async fn top_source_ips(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    limit: i64,
+) -> Result<Vec<SourceIpCount>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	source_ip as ip,
+	count(*) as count
+from
+	filtered_log
+where
+	source_ip is not null
+	and processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tsource_ip\norder by\n\tcount desc\nlimit ")
+        .push_bind(limit)
+        .push(";\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// counts payments per amount bucket, `bucket` being the 1-based `width_bucket` index over the
+/// ascending `thresholds`, 0 meaning below the first one
+pub async fn amount_histogram(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    thresholds: &[Decimal],
+) -> Result<Vec<HistogramCount>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	processed_by as name,
+	width_bucket(amount, "#,
+        )
+        .push_bind(thresholds)
+        .push(
+            r#") as bucket,
+	count(*) as count
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tname,\n\tbucket;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// failed attempts against each processor for payments in the window, whatever their outcome
+pub async fn failed_attempts(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<FailedAttempts>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	attempts.processor as name,
+	count(*) as count,
+	(array_agg(attempts.error order by attempts.attempted_at desc))[1] as last_error
+from
+	filtered_log
+	join payments.processor_attempts attempts on attempts.payment_id = filtered_log.id
+where
+	not attempts.succeeded
+	and attempts.processor = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tattempts.processor;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// gaps longer than `threshold_secs` between consecutive processed payments, in order, the
+/// first `limit` of them
+pub async fn idle_periods(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    threshold_secs: f64,
+    limit: i64,
+) -> Result<Vec<IdlePeriod>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#", gaps as (
+	select
+		lag(requested_at) over (order by requested_at) as start,
+		requested_at as end
+	from
+		filtered_log
+	where
+		processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(
+            r#")
+)
+select
+	start,
+	"end",
+	floor(extract(epoch from "end" - start) / 60)::int8 as duration_minutes
+from
+	gaps
+where
+	"end" - start > make_interval(secs => "#,
+        )
+        .push_bind(threshold_secs)
+        .push(")\norder by\n\tstart\nlimit ")
+        .push_bind(limit)
+        .push(";\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// failed attempts against each processor by what went wrong, told apart by how
+/// [`crate::processor`] words the error
+pub async fn failure_modes(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<FailureModeCount>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	attempts.processor as name,
+	case
+		when attempts.error like 'timed out%' then 'timeout'
+		when attempts.error like 'status 4%' then 'http_4xx'
+		when attempts.error like 'status 5%' then 'http_5xx'
+		when attempts.error like 'connection failed%' then 'connection_error'
+		else 'other'
+	end as mode,
+	count(*) as count
+from
+	filtered_log
+	join payments.processor_attempts attempts on attempts.payment_id = filtered_log.id
+where
+	not attempts.succeeded
+	and attempts.processor = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\t1, 2;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// payments each processor ended up processing that needed more than one attempt, wherever
+/// the earlier ones went
+pub async fn retried_payments(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<RetriedPayments>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	filtered_log.processed_by as name,
+	count(distinct attempts.payment_id) as count,
+	count(distinct filtered_log.id) as total
+from
+	filtered_log
+	left join payments.processor_attempts attempts on attempts.payment_id = filtered_log.id
+	and attempts.attempt > 1
+where
+	filtered_log.processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(
+            ")
+group by
+	filtered_log.processed_by;
+",
+        );
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// http requests made to each processor for payments in the window. a retry is any attempt past
+/// a payment's first, whichever processor that first attempt went to
+pub async fn network_stats(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<NetworkStats>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	attempts.processor as name,
+	count(*) as total_http_requests,
+	count(*) filter (where attempts.attempt > 1) as total_retries,
+	(count(*) filter (where attempts.attempt > 1))::float8 / count(distinct attempts.payment_id) as avg_retry_count
+from
+	filtered_log
+	join payments.processor_attempts attempts on attempts.payment_id = filtered_log.id
+where
+	attempts.processor = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tattempts.processor;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// out of the payments in the window each processor was tried for, the share it ended up
+/// processing, and how many requests it took per payment
+pub async fn processing_efficiency(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<ProcessingEfficiency>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	attempts.processor as name,
+	(count(distinct attempts.payment_id) filter (where attempts.succeeded))::float8 / count(distinct attempts.payment_id) as success_rate,
+	count(*)::float8 / count(distinct attempts.payment_id) as avg_attempts_per_payment
+from
+	filtered_log
+	join payments.processor_attempts attempts on attempts.payment_id = filtered_log.id
+where
+	attempts.processor = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tattempts.processor;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// mean, median and stddev are rounded to cents
+pub async fn amount_stats(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<AmountStats>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	processed_by as name,
+	min(amount) as min,
+	max(amount) as max,
+	round(avg(amount), 2) as mean,
+	round((percentile_cont(0.5) within group (order by amount))::numeric, 2) as median,
+	round(stddev_pop(amount), 2) as stddev
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tprocessed_by;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// interpolated quartiles, rounded to cents
+pub async fn amount_quartiles(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<AmountQuartiles>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	processed_by as name,
+	round((percentile_cont(0.25) within group (order by amount))::numeric, 2) as q1,
+	round((percentile_cont(0.5) within group (order by amount))::numeric, 2) as q2,
+	round((percentile_cont(0.75) within group (order by amount))::numeric, 2) as q3
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(
+            ")
+group by
+	processed_by;
+",
+        );
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// how many payments' correlation ids start with each hex digit
+pub async fn correlation_id_nibbles(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<i64>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push("select\n\tcount(*)\nfrom\n\tfiltered_log\nwhere\n\tprocessed_by = any(")
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tleft(id::text, 1);\n");
+
+    query.build_query_scalar().fetch_all(db).await
+}
+
+/// correlation ids by uuid version, the digit right after the second dash
+pub async fn uuid_versions(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<UuidVersions>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	processed_by as name,
+	count(*) filter (where substr(id::text, 15, 1) = '4') as v4,
+	count(*) filter (where substr(id::text, 15, 1) = '7') as v7,
+	count(*) filter (where substr(id::text, 15, 1) not in ('4', '7')) as other
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tprocessed_by;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// seconds from the start of the window until each processor's first payment
+pub async fn time_to_first_payment(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<TimeToFirstPayment>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push("select\n\tprocessed_by as name,\n\textract(epoch from min(requested_at) - ")
+        .push_bind(filter.from)
+        .push(
+            r#")::float8 as seconds
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tprocessed_by;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// payments that took longer than `threshold_secs` to be processed, with the ids of the first
+/// `limit` of them
+pub async fn sla_breaches(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    threshold_secs: f64,
+    limit: i64,
+) -> Result<Vec<SlaBreaches>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#", breaches as (
+	select
+		processed_by,
+		id,
+		requested_at,
+		extract(epoch from processed_at - requested_at) > "#,
+        )
+        .push_bind(threshold_secs)
+        .push(
+            r#" as breached
+	from
+		filtered_log
+	where
+		processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(
+            r#")
+)
+select
+	processed_by as name,
+	count(*) filter (where breached) as count,
+	count(*) as total,
+	coalesce((array_agg(id order by requested_at) filter (where breached))[1:"#,
+        )
+        .push_bind(limit)
+        .push(
+            r#"], '{}') as ids
+from
+	breaches
+group by
+	processed_by;
+"#,
+        );
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// totals across every processor for each day of the week with payments, in utc
+pub async fn day_of_week_totals(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<DayOfWeekTotals>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	extract(dow from requested_at at time zone 'utc')::int4 as day_of_week,
+	sum(amount) as total_amount,
+	count(*) as total_requests
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(
+            ")
+group by
+	day_of_week;
+",
+        );
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// requests across every processor for each hour of the day with payments, in utc
+pub async fn hour_of_day_counts(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<HourOfDayCount>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	extract(hour from requested_at at time zone 'utc')::int4 as hour,
+	count(*) as total_requests
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(
+            ")
+group by
+	hour;
+",
+        );
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// the first `limit` correlation ids each processor handled, oldest first unless `shuffled`
+pub async fn payment_ids(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    limit: i64,
+    shuffled: bool,
+) -> Result<Vec<PaymentIds>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    if shuffled {
+        query.push(
+            "
+select
+	name,
+	array(select id from unnest(ids) id order by random()) as ids
+from
+	(
+",
+        );
+    }
+    // aggregates take no limit, so the sorted array gets sliced instead
+    query
+        .push(
+            "select
+	processed_by as name,
+	(array_agg(id order by requested_at))[1:",
+        )
+        .push_bind(limit)
+        .push(
+            r#"] as ids
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(
+            ")
+group by
+	processed_by",
+        );
+    if shuffled {
+        query.push("\n\t) oldest");
+    }
+    query.push(";\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// coefficient of variation of the time between each processor's consecutive payments, null
+/// until there are enough of them to vary or when they all arrived at once
+pub async fn burstiness(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<Burstiness>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#", inter_arrivals as (
+	select
+		processed_by,
+		extract(epoch from requested_at - lag(requested_at) over (
+			partition by processed_by
+			order by requested_at
+		))::float8 as seconds
+	from
+		filtered_log
+	where
+		processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(
+            r#")
+)
+select
+	processed_by as name,
+	stddev_samp(seconds) / nullif(avg(seconds), 0) as coefficient
+from
+	inter_arrivals
+group by
+	processed_by;
+"#,
+        );
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// payments per minute over the last `window_minutes`, counting back from now rather than `to`
+pub async fn velocity(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    window_minutes: u32,
+) -> Result<Vec<Velocity>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push("select\n\tprocessed_by as name,\n\tcount(*)::float8 / ")
+        .push_bind(window_minutes as f64)
+        .push(
+            r#" as per_minute
+from
+	filtered_log
+where
+	requested_at >= now() - make_interval(mins => "#,
+        )
+        .push_bind(window_minutes as i32)
+        .push(")\n\tand processed_by = any(")
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tprocessed_by;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// payments above `threshold` per payment at or below it, null when there are none below
+pub async fn high_to_low_ratio(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+    threshold: Decimal,
+) -> Result<Vec<HighToLowRatio>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push("select\n\tprocessed_by as name,\n\tcount(*) filter (where amount > ")
+        .push_bind(threshold)
+        .push(")::float8 / nullif(count(*) filter (where amount <= ")
+        .push_bind(threshold)
+        .push(
+            r#"), 0) as ratio
+from
+	filtered_log
+where
+	processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tprocessed_by;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+pub async fn source_ip_totals(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<SourceIpTotals>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	source_ip as ip,
+	sum(amount) as total_amount,
+	count(amount) as total_requests
+from
+	filtered_log
+where
+	source_ip is not null
+	and processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tsource_ip;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
+
+/// p50 of the time between receiving and processing each payment
+pub async fn median_latency(
+    db: &Pool<Postgres>,
+    filter: &SummaryFilter,
+) -> Result<Vec<ProcessorLatency>, sqlx::Error> {
+    let mut query = filtered_log(filter, None);
+    query
+        .push(
+            r#"select
+	processed_by as name,
+	percentile_cont(0.5) within group (
+		order by
+			extract(epoch from processed_at - requested_at) * 1000
+	) as latency_ms
+from
+	filtered_log
+where
+	processed_at is not null
+	and processed_by = any("#,
+        )
+        .push_bind(&filter.processor_names)
+        .push(")\ngroup by\n\tprocessed_by;\n");
+
+    query.build_query_as().fetch_all(db).await
+}
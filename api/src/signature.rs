@@ -0,0 +1,23 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config;
+
+pub const SIGNATURE_HEADER: &str = "x-signature";
+pub const NONCE_HEADER: &str = "x-nonce";
+
+/// hex encoded `HMAC-SHA256(nonce + ":" + body, key)`, or of just the body when there's no nonce,
+/// `None` when no signing key is configured
+pub fn sign(body: &[u8], nonce: Option<&str>) -> Option<String> {
+    let key = config().signing_key.as_deref()?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("hmac accepts keys of any size");
+
+    if let Some(nonce) = nonce {
+        mac.update(nonce.as_bytes());
+        mac.update(b":");
+    }
+    mac.update(body);
+
+    Some(hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,2702 @@
+use arrow::{
+    array::{ArrayRef, Decimal128Array, Int64Array, StringArray},
+    datatypes::{DataType, Field, Schema},
+    error::ArrowError,
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+};
+use async_stream::stream;
+use axum::{
+    Json,
+    body::Body,
+    extract::{Query, RawQuery},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, FixedOffset, NaiveDate, Offset, SecondsFormat, TimeDelta, TimeZone, Utc};
+use num_format::{Locale, ToFormattedString};
+use parquet::{
+    arrow::ArrowWriter,
+    errors::ParquetError,
+    file::{metadata::KeyValue, properties::WriterProperties},
+};
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use reqwest::Url;
+use rust_decimal::prelude::ToPrimitive;
+use serde::Deserialize;
+use serde_json::json;
+use sqlformat::{FormatOptions, QueryParams};
+use sqlx::types::{Decimal, JsonValue};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use uuid::Uuid;
+
+use crate::{
+    ProcessorRuntime, admin, config, db,
+    error::ApiError,
+    external_processors, geoip, geoip_db, http_client, in_flight, payments,
+    repository::{self, BucketSize, ProcessorLatency, Since, SummaryFilter},
+    sender,
+    signature::{self, NONCE_HEADER, SIGNATURE_HEADER},
+    summary_cache,
+    time_series::TimeSeries,
+};
+
+const MAX_LAST_N: i64 = 100_000;
+const MIN_SAMPLE_RATE: f64 = 0.001;
+const MAX_SIMULATED_LAG_MS: u64 = 30_000;
+const TOP_SOURCE_IPS: i64 = 10;
+const MAX_COMPARED_PROCESSORS: usize = 5;
+const MAX_METADATA_FILTER_BYTES: usize = 1024;
+const MAX_METADATA_FILTER_KEYS: usize = 5;
+const DEFAULT_VELOCITY_WINDOW_MINUTES: u32 = 5;
+const MAX_VELOCITY_WINDOW_MINUTES: u32 = 24 * 60;
+const LOW_CORRELATION_ENTROPY: f64 = 3.5;
+const MIN_CORRELATION_ENTROPY_SAMPLE: i64 = 100;
+const HIGH_AMOUNT_THRESHOLD: Decimal = Decimal::from_parts(500, 0, 0, false, 0);
+const DEFAULT_RAW_PAYMENTS_LIMIT: i64 = 100;
+const MAX_RAW_PAYMENTS_LIMIT: i64 = 1_000;
+/// in postgres `dow` order
+const DAYS_OF_WEEK: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const DEFAULT_FORECAST_HORIZON_MINUTES: u64 = 60;
+const MAX_FORECAST_HORIZON_MINUTES: u64 = 7 * 24 * 60;
+/// a week of minutes, the series is dense so a sparse window can still span too many
+const MAX_TIME_SERIES_BUCKETS: usize = 7 * 24 * 60;
+const DEFAULT_SLA_THRESHOLD_SECS: u64 = 30;
+const MAX_SLA_BREACH_IDS: i64 = 10;
+const DEFAULT_IDLE_THRESHOLD_MINUTES: u64 = 5;
+const MAX_IDLE_PERIODS: i64 = 100;
+const DEFAULT_PAYMENT_IDS_LIMIT: i64 = 50;
+const MAX_PAYMENT_IDS_LIMIT: i64 = 500;
+const FAILURE_MODES: [&str; 5] = [
+    "timeout",
+    "http_4xx",
+    "http_5xx",
+    "connection_error",
+    "other",
+];
+const PROJECTABLE_FIELDS: &[&str] = &[
+    "totalAmount",
+    "totalRequests",
+    "endpoint",
+    "weight",
+    "amountHistogram",
+    "amountStats",
+    "networkStats",
+    "highToLowRatio",
+    "currentVelocityPerMinute",
+    "timeToFirstPaymentSeconds",
+    "burstinessCoefficient",
+    "sloCompliance",
+    "paymentIds",
+    "slaBreaches",
+    "retriedPayments",
+    "amountQuartiles",
+    "outlierBounds",
+    "uuidVersionBreakdown",
+    "processingEfficiency",
+    "failureModes",
+    "peakConcurrency",
+    "rank",
+    "rankByAmount",
+];
+const MAX_CORRELATION_IDS: usize = 100;
+const MAX_HISTOGRAM_BUCKETS: usize = 20;
+const DEFAULT_HISTOGRAM_BUCKETS: &str = "0,10,50,100,500,1000,infinity";
+
+#[derive(Deserialize)]
+pub struct SummaryParams {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    last_n: Option<i64>,
+    #[serde(default)]
+    alert_if_zero: bool,
+    nonce: Option<String>,
+    #[serde(default)]
+    time_series: bool,
+    granularity: Option<Granularity>,
+    bucket: Option<Bucket>,
+    #[serde(default)]
+    include_running_total: bool,
+    #[serde(default)]
+    include_momentum: bool,
+    #[serde(default)]
+    include_processor_config: bool,
+    #[serde(default)]
+    rollup: bool,
+    sample_rate: Option<f64>,
+    #[serde(default)]
+    format: Format,
+    exclude_processors: Option<String>,
+    #[serde(default)]
+    include_cancelled: bool,
+    #[serde(default)]
+    output_currency_symbol: bool,
+    locale: Option<String>,
+    compare_processors: Option<String>,
+    metadata_filter: Option<String>,
+    moving_average: Option<u32>,
+    #[serde(default)]
+    pivot: bool,
+    since_cursor: Option<String>,
+    #[serde(default)]
+    explain: bool,
+    #[serde(default)]
+    randomize_order: bool,
+    simulate_lag_ms: Option<u64>,
+    #[serde(default)]
+    include_source_ip: bool,
+    max_processor_latency_ms: Option<u64>,
+    #[serde(default)]
+    include_histogram: bool,
+    histogram_buckets: Option<String>,
+    #[serde(default)]
+    include_failed_processor_attempts: bool,
+    correlation_ids: Option<String>,
+    #[serde(default)]
+    include_db_row_version: bool,
+    #[serde(default)]
+    compute_gini: bool,
+    #[serde(default)]
+    include_duplicate_correlation_ids: bool,
+    #[serde(default)]
+    recompute: bool,
+    #[serde(default)]
+    include_network_stats: bool,
+    time_offset: Option<String>,
+    #[serde(default)]
+    include_amount_stats: bool,
+    #[serde(default = "default_true")]
+    output_null_as_zero: bool,
+    #[serde(default)]
+    include_p2p_ratio: bool,
+    group_by: Option<String>,
+    #[serde(default)]
+    include_velocity: bool,
+    velocity_window_minutes: Option<u32>,
+    #[serde(default)]
+    include_correlation_entropy: bool,
+    #[serde(default)]
+    include_correlation_id_patterns: bool,
+    #[serde(default)]
+    include_processing_efficiency: bool,
+    #[serde(default)]
+    include_failure_modes: bool,
+    #[serde(default)]
+    include_idle_periods: bool,
+    #[serde(default)]
+    include_concurrent_load: bool,
+    #[serde(default)]
+    include_processor_rank: bool,
+    idle_threshold_minutes: Option<u64>,
+    #[serde(default)]
+    export_to_webhook: bool,
+    webhook_url: Option<String>,
+    #[serde(default)]
+    include_processing_queue_depth: bool,
+    #[serde(default)]
+    include_time_to_first_payment: bool,
+    #[serde(default)]
+    include_burstiness: bool,
+    #[serde(default)]
+    watermark: bool,
+    #[serde(default)]
+    include_geo: bool,
+    #[serde(default)]
+    include_time_zones: bool,
+    projection: Option<String>,
+    #[serde(default)]
+    deduplicate: bool,
+    #[serde(default)]
+    include_raw_payments: bool,
+    raw_payments_limit: Option<i64>,
+    #[serde(default)]
+    include_payment_ids: bool,
+    #[serde(default)]
+    include_change_events: bool,
+    #[serde(default)]
+    forecast: bool,
+    forecast_horizon_minutes: Option<u64>,
+    #[serde(default)]
+    include_retry_payments: bool,
+    #[serde(default)]
+    include_amount_quartiles: bool,
+    #[serde(default)]
+    include_day_of_week: bool,
+    #[serde(default)]
+    include_hour_of_day: bool,
+    #[serde(default)]
+    include_sla_breaches: bool,
+    sla_threshold_secs: Option<u64>,
+    payment_ids_limit: Option<i64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Graphql,
+    Ndjson,
+    Yaml,
+    Arrow,
+    Parquet,
+    Sql,
+}
+
+pub enum SummaryError {
+    BadRequest(&'static str),
+    Internal(&'static str),
+    NoPayments {
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    },
+}
+
+impl SummaryError {
+    fn message(&self) -> String {
+        match self {
+            SummaryError::BadRequest(message) | SummaryError::Internal(message) => {
+                message.to_string()
+            }
+            SummaryError::NoPayments { .. } => "no payments processed in window".to_string(),
+        }
+    }
+}
+
+/// logs what the db failed at, the client only ever hears the summary failed
+fn internal(context: &'static str) -> impl FnOnce(sqlx::Error) -> SummaryError {
+    move |error| {
+        tracing::error!("{}, {}", context, error);
+        SummaryError::Internal("failed fetching summary")
+    }
+}
+
+impl IntoResponse for SummaryError {
+    fn into_response(self) -> Response {
+        match self {
+            SummaryError::BadRequest(message) => {
+                ApiError::InvalidInput(message.to_string()).into_response()
+            }
+            // every internal failure here is the db failing to answer
+            SummaryError::Internal(_) => ApiError::DatabaseUnavailable.into_response(),
+            SummaryError::NoPayments { from, to } => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({
+                    "error": self.message(),
+                    "code": "no_payments",
+                    "from": from,
+                    "to": to,
+                })),
+            )
+                .into_response(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Minute,
+    Hour,
+    #[serde(rename = "6h")]
+    SixHours,
+    Day,
+    Auto,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    fn size(self) -> BucketSize {
+        match self {
+            Bucket::Minute => BucketSize::Minute,
+            Bucket::Hour => BucketSize::Hour,
+            Bucket::Day => BucketSize::Day,
+        }
+    }
+}
+
+impl Granularity {
+    /// picks a bucket size that keeps the number of data points manageable for the window
+    fn resolve(self, from: DateTime<Utc>, to: DateTime<Utc>) -> BucketSize {
+        let window = to - from;
+        match self {
+            Granularity::Minute => BucketSize::Minute,
+            Granularity::Hour => BucketSize::Hour,
+            Granularity::SixHours => BucketSize::SixHours,
+            Granularity::Day => BucketSize::Day,
+            Granularity::Auto if window < TimeDelta::hours(1) => BucketSize::Minute,
+            Granularity::Auto if window <= TimeDelta::days(1) => BucketSize::Hour,
+            Granularity::Auto if window <= TimeDelta::days(7) => BucketSize::SixHours,
+            Granularity::Auto => BucketSize::Day,
+        }
+    }
+}
+
+pub async fn summary(
+    Query(params): Query<SummaryParams>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Response {
+    // forcing a fresh query on every call would defeat the cache, so only admins get to
+    if params.recompute && !admin::authorized(&headers) {
+        return ApiError::Unauthorized.into_response();
+    }
+
+    // larger pages of raw payments are for operators only
+    if params.include_raw_payments
+        && params
+            .raw_payments_limit
+            .unwrap_or(DEFAULT_RAW_PAYMENTS_LIMIT)
+            > DEFAULT_RAW_PAYMENTS_LIMIT
+        && !admin::authorized(&headers)
+    {
+        return ApiError::Unauthorized.into_response();
+    }
+
+    let webhook_url = match (params.export_to_webhook, params.webhook_url.as_deref()) {
+        // the api would post wherever it's told to, so that is an admin thing too
+        (true, Some(_)) if !admin::authorized(&headers) => {
+            return ApiError::Unauthorized.into_response();
+        }
+        (true, Some(url)) => match Url::parse(url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Some(url),
+            _ => return SummaryError::BadRequest("invalid webhook_url").into_response(),
+        },
+        _ => None,
+    };
+
+    let nonce = params.nonce.as_deref();
+    let cache_key = cache_key(query.as_deref().unwrap_or_default());
+    let cached = (!params.recompute)
+        .then(|| summary_cache().get(&cache_key))
+        .flatten();
+
+    let built = match cached {
+        Some(summary) => Ok(summary),
+        None => build_summary(&params).await.inspect(|summary| {
+            summary_cache().put(cache_key, summary);
+        }),
+    };
+
+    if let Some(url) = webhook_url {
+        return match built {
+            Ok(summary) => export(summary, url, params.nonce.clone()),
+            Err(error) => error.into_response(),
+        };
+    }
+
+    match (params.format, built) {
+        (Format::Json, Ok(summary)) => signed(&summary, nonce),
+        (
+            Format::Json
+            | Format::Ndjson
+            | Format::Yaml
+            | Format::Arrow
+            | Format::Parquet
+            | Format::Sql,
+            Err(error),
+        ) => error.into_response(),
+        (Format::Sql, Ok(summary)) => sql(summary, &params),
+        (Format::Parquet, Ok(summary)) => parquet(summary, &processor_keys(&params)),
+        (Format::Arrow, Ok(summary)) => arrow(summary, &processor_keys(&params)),
+        (Format::Yaml, Ok(summary)) => yaml(summary),
+        (Format::Ndjson, Ok(summary)) => ndjson(summary, processor_keys(&params)),
+        // graphql reports failures in the envelope, never through the status code
+        (Format::Graphql, Ok(summary)) => signed(
+            &json!({ "data": { "paymentsSummary": summary }, "errors": null }),
+            nonce,
+        ),
+        (Format::Graphql, Err(error)) => signed(
+            &json!({
+                "data": null,
+                "errors": [{
+                    "message": error.message(),
+                    "locations": [],
+                    "path": ["paymentsSummary"],
+                }],
+            }),
+            nonce,
+        ),
+    }
+}
+
+async fn build_summary(params: &SummaryParams) -> Result<JsonValue, SummaryError> {
+    let time_offset = match params.time_offset.as_deref().map(fixed_offset) {
+        Some(Some(offset)) => Some(offset),
+        Some(None) => return Err(SummaryError::BadRequest("invalid time_offset")),
+        None => None,
+    };
+    let group_by = GroupBy::parse(params.group_by.as_deref())?;
+    let projection = projection(params.projection.as_deref())?;
+
+    let locale = match params.locale.as_deref().map(resolve_locale) {
+        Some(Some(locale)) => Some(locale),
+        Some(None) => return Err(SummaryError::BadRequest("unsupported locale")),
+        None => None,
+    };
+    let sample_rate = match params.sample_rate {
+        Some(rate) if !rate.is_finite() => {
+            return Err(SummaryError::BadRequest("sample_rate must be a number"));
+        }
+        Some(rate) => Some(rate.clamp(MIN_SAMPLE_RATE, 1.0)).filter(|&rate| rate < 1.0),
+        None => None,
+    };
+    if params.explain && !config().enable_debug_endpoints {
+        return Err(SummaryError::BadRequest("explain requires debug endpoints"));
+    }
+
+    let time_series = params.time_series || params.granularity.is_some();
+    if params.forecast && !time_series {
+        return Err(SummaryError::BadRequest("forecast requires time_series"));
+    }
+    let horizon_minutes = params
+        .forecast_horizon_minutes
+        .unwrap_or(DEFAULT_FORECAST_HORIZON_MINUTES);
+    if params.forecast && !(1..=MAX_FORECAST_HORIZON_MINUTES).contains(&horizon_minutes) {
+        return Err(SummaryError::BadRequest(
+            "forecast_horizon_minutes out of range",
+        ));
+    }
+    if params.include_running_total && !time_series {
+        return Err(SummaryError::BadRequest(
+            "include_running_total requires time_series",
+        ));
+    }
+    if params.include_momentum && !time_series {
+        return Err(SummaryError::BadRequest(
+            "include_momentum requires time_series",
+        ));
+    }
+    if params.simulate_lag_ms.is_some() && !config().enable_debug_endpoints {
+        return Err(SummaryError::BadRequest(
+            "simulate_lag_ms requires debug endpoints",
+        ));
+    }
+
+    let mut from = params
+        .from
+        .unwrap_or_else(|| DateTime::<Utc>::from_str("0000-01-01T00:00:00.000Z").unwrap());
+    let mut to = params
+        .to
+        .unwrap_or_else(|| DateTime::<Utc>::from_str("9999-12-31T23:59:59.999Z").unwrap());
+
+    if let Some(last_n) = params.last_n {
+        if !(1..=MAX_LAST_N).contains(&last_n) {
+            return Err(SummaryError::BadRequest("last_n out of range"));
+        }
+
+        let window = repository::last_n_window(db(), last_n)
+            .await
+            .map_err(internal("failed fetching window for last_n payments"))?;
+        // `to` is exclusive, nudge it past the newest payment so it is counted
+        if let Some((first, last)) = window {
+            (from, to) = (first, last + TimeDelta::microseconds(1));
+        }
+    }
+
+    let mut processors = selected_processors(params)?;
+    let mut filter = SummaryFilter {
+        processor_names: processors.iter().map(|p| p.name.clone()).collect(),
+        from,
+        to,
+        metadata: metadata_filter(params.metadata_filter.as_deref())?,
+        since: since_cursor(params.since_cursor.as_deref())?,
+        ids: correlation_ids(params.correlation_ids.as_deref())?,
+    };
+
+    let mut excluded_due_to_latency = Vec::new();
+    if let Some(max_latency_ms) = params.max_processor_latency_ms {
+        let latencies = repository::median_latency(db(), &filter)
+            .await
+            .map_err(internal("failed fetching processor latencies"))?;
+        excluded_due_to_latency = latencies
+            .into_iter()
+            .filter(|latency| {
+                latency
+                    .latency_ms
+                    .is_some_and(|ms| ms > max_latency_ms as f64)
+            })
+            .map(|latency| latency.name)
+            .collect();
+
+        processors.retain(|p| !excluded_due_to_latency.contains(&p.name));
+        filter
+            .processor_names
+            .retain(|name| !excluded_due_to_latency.contains(name));
+    }
+
+    let processor_names = filter.processor_names.clone();
+
+    // ahead of every shape the summary can take, and only counting processed payments
+    if params.alert_if_zero {
+        let rollup = repository::summary_rollup(db(), &filter, true)
+            .await
+            .map_err(internal("failed counting payments in window"))?;
+        if rollup.total_requests == 0 {
+            return Err(SummaryError::NoPayments { from, to });
+        }
+    }
+
+    if params.rollup {
+        let rollup = repository::summary_rollup(db(), &filter, params.output_null_as_zero)
+            .await
+            .map_err(internal("failed fetching summary rollup"))?;
+        return Ok(json!({
+            "totalAmount": rollup.total_amount.map_or(JsonValue::Null, amount),
+            "totalRequests": rollup.total_requests,
+            "processorCount": processor_names.len(),
+        }));
+    }
+
+    if group_by.day {
+        let days = repository::daily_summary(db(), &filter)
+            .await
+            .map_err(internal("failed fetching daily summary"))?;
+        return Ok(daily_summary(&days, &processor_names, group_by.processor));
+    }
+
+    if let Some(bucket) = params.bucket {
+        let buckets = repository::summary_bucketed(db(), &filter, bucket.size())
+            .await
+            .map_err(internal("failed fetching bucketed summary"))?;
+        return Ok(bucketed_summary(&buckets, &processor_names));
+    }
+
+    if params.explain {
+        let plan = repository::summary_plan(db(), &filter, sample_rate, params.output_null_as_zero)
+            .await
+            .map_err(internal("failed explaining summary"))?;
+        return Ok(json!({ "queryPlan": plan }));
+    }
+
+    let mut summary = repository::summary(db(), &filter, sample_rate, params.output_null_as_zero)
+        .await
+        .map_err(internal("failed fetching summary"))?;
+
+    if params.last_n.is_some() {
+        summary["from"] = json!(from);
+        summary["to"] = json!(to);
+    }
+
+    // nothing new, the client keeps polling from the same cursor, in the form it sent
+    match (&params.since_cursor, filter.since) {
+        (_, Some(Since::RequestedAfter(since))) => {
+            let last = repository::last_requested_at(db(), &filter)
+                .await
+                .map_err(internal("failed fetching next summary cursor"))?;
+            summary["nextCursor"] = json!(last.filter(|&last| last > since).unwrap_or(since))
+        }
+        (Some(cursor), since) => {
+            let last = repository::last_processed(db(), &filter)
+                .await
+                .map_err(internal("failed fetching next summary cursor"))?;
+            let newer = |last: &(DateTime<Utc>, Uuid)| match since {
+                Some(Since::ProcessedAfter(processed_at, id)) => *last > (processed_at, id),
+                _ => true,
+            };
+            summary["nextCursor"] = json!(last.filter(newer).map_or(
+                cursor.clone(),
+                |(processed_at, id)| payments::encode_cursor(processed_at, id)
+            ))
+        }
+        (None, _) => {}
+    }
+
+    if let Some(ids) = &filter.ids {
+        let found = repository::found_ids(db(), &filter)
+            .await
+            .map_err(internal("failed counting found correlation ids"))?;
+        summary["requestedIds"] = json!(ids.len());
+        summary["foundIds"] = json!(found);
+    }
+
+    if params.include_duplicate_correlation_ids {
+        let count = repository::duplicate_count(db(), &filter)
+            .await
+            .map_err(internal("failed counting duplicates"))?;
+        summary["duplicateCount"] = json!(count);
+    }
+
+    if params.include_db_row_version {
+        let version = repository::max_row_version(db(), &filter)
+            .await
+            .map_err(internal("failed fetching max row version"))?;
+        summary["maxRowVersion"] = json!(version);
+    }
+
+    if params.watermark {
+        let watermark = repository::processing_watermark(db(), &filter)
+            .await
+            .map_err(internal("failed fetching processing watermark"))?;
+        summary["processingWatermark"] = json!(watermark);
+    }
+
+    if params.include_change_events {
+        let events = repository::config_events(db(), &filter)
+            .await
+            .map_err(internal("failed fetching config events"))?;
+        summary["configChanges"] = json!(events);
+    }
+
+    if params.include_raw_payments {
+        let limit = params
+            .raw_payments_limit
+            .unwrap_or(DEFAULT_RAW_PAYMENTS_LIMIT);
+        if !(1..=MAX_RAW_PAYMENTS_LIMIT).contains(&limit) {
+            return Err(SummaryError::BadRequest("raw_payments_limit out of range"));
+        }
+
+        let payments = repository::raw_payments(db(), &filter, limit, params.randomize_order)
+            .await
+            .map_err(internal("failed fetching raw payments"))?;
+        summary["payments"] = json!(payments);
+    }
+
+    if params.include_processing_queue_depth {
+        summary["currentQueueDepth"] = json!(sender().len());
+        summary["inFlightCount"] = json!(in_flight());
+    }
+
+    if params.max_processor_latency_ms.is_some() {
+        summary["excludedDueToLatency"] = json!(excluded_due_to_latency);
+    }
+
+    if let Some(rate) = sample_rate {
+        summary["approximate"] = json!(true);
+        summary["sample_rate"] = json!(rate);
+    }
+
+    if params.include_processor_config {
+        for processor in &processors {
+            if !config().hide_endpoint_urls {
+                summary[&processor.name]["endpoint"] = json!(processor.endpoint);
+            }
+            summary[&processor.name]["weight"] = json!(processor.weight);
+        }
+    }
+
+    let (slo_names, slo_max_latency_ms): (Vec<String>, Vec<i64>) = processors
+        .iter()
+        .filter_map(|p| Some((p.name.clone(), p.slo_max_latency_ms? as i64)))
+        .unzip();
+
+    if !slo_names.is_empty() {
+        let compliances =
+            repository::slo_compliance(db(), &filter, &slo_names, &slo_max_latency_ms)
+                .await
+                .map_err(internal("failed fetching slo compliance"))?;
+        for slo in compliances {
+            summary[&slo.name]["sloCompliance"] = json!(slo.compliance);
+        }
+    }
+
+    if params.include_cancelled {
+        let cancelled = repository::cancelled_summary(db(), &filter, params.output_null_as_zero)
+            .await
+            .map_err(internal("failed fetching cancelled summary"))?;
+        summary["cancelled"] = json!({
+            "totalAmount": cancelled.total_amount.map_or(JsonValue::Null, amount),
+            "totalRequests": cancelled.total_requests,
+            "cancelledBy": {
+                "user": cancelled.by_user,
+                "system": cancelled.by_system,
+            },
+        })
+    }
+
+    if params.include_source_ip {
+        let ips = repository::top_source_ips(db(), &filter, TOP_SOURCE_IPS)
+            .await
+            .map_err(internal("failed fetching top source ips"))?;
+        summary["topSourceIps"] = ips
+            .iter()
+            .map(|source| {
+                let ip = if config().mask_ips {
+                    mask_ip(source.ip)
+                } else {
+                    source.ip
+                };
+                json!({ "ip": ip, "count": source.count })
+            })
+            .collect();
+    }
+
+    if params.include_histogram || params.histogram_buckets.is_some() {
+        let buckets = HistogramBuckets::parse(
+            params
+                .histogram_buckets
+                .as_deref()
+                .unwrap_or(DEFAULT_HISTOGRAM_BUCKETS),
+        )?;
+
+        let counts = repository::amount_histogram(db(), &filter, &buckets.thresholds)
+            .await
+            .map_err(internal("failed fetching amount histogram"))?;
+        for name in &processor_names {
+            summary[name]["amountHistogram"] = buckets.to_json(name, &counts);
+        }
+    }
+
+    if params.include_failed_processor_attempts {
+        let failed = repository::failed_attempts(db(), &filter)
+            .await
+            .map_err(internal("failed fetching failed processor attempts"))?;
+        summary["failedAttempts"] = processor_names
+            .iter()
+            .map(|name| {
+                let failed = failed.iter().find(|failed| &failed.name == name);
+                let attempts = json!({
+                    "count": failed.map_or(0, |failed| failed.count),
+                    "lastError": failed.and_then(|failed| failed.last_error.as_deref()),
+                });
+                (name.clone(), attempts)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+    }
+
+    if params.include_failure_modes {
+        let counts = repository::failure_modes(db(), &filter)
+            .await
+            .map_err(internal("failed fetching failure modes"))?;
+        for name in &processor_names {
+            let mut modes = json!({});
+            for mode in FAILURE_MODES {
+                modes[mode] = json!(
+                    counts
+                        .iter()
+                        .find(|count| &count.name == name && count.mode == mode)
+                        .map_or(0, |count| count.count)
+                );
+            }
+            summary[name]["failureModes"] = modes;
+        }
+    }
+
+    if params.include_idle_periods {
+        let threshold_minutes = params
+            .idle_threshold_minutes
+            .unwrap_or(DEFAULT_IDLE_THRESHOLD_MINUTES);
+        if threshold_minutes == 0 {
+            return Err(SummaryError::BadRequest(
+                "idle_threshold_minutes must be positive",
+            ));
+        }
+
+        let periods = repository::idle_periods(
+            db(),
+            &filter,
+            threshold_minutes as f64 * 60.0,
+            MAX_IDLE_PERIODS,
+        )
+        .await
+        .map_err(internal("failed fetching idle periods"))?;
+        summary["idlePeriods"] = periods
+            .iter()
+            .map(|period| {
+                json!({
+                    "start": period.start,
+                    "end": period.end,
+                    "durationMinutes": period.duration_minutes,
+                })
+            })
+            .collect();
+    }
+
+    if params.include_concurrent_load {
+        let intervals = repository::processing_intervals(db(), &filter)
+            .await
+            .map_err(internal("failed fetching processing intervals"))?;
+        for name in &processor_names {
+            let intervals: Vec<_> = intervals
+                .iter()
+                .filter(|interval| &interval.name == name)
+                .map(|interval| (interval.requested_at, interval.processed_at))
+                .collect();
+            summary[name]["peakConcurrency"] = json!(peak_concurrency(&intervals));
+        }
+    }
+
+    if params.compute_gini {
+        let amounts = repository::sorted_amounts(db(), &filter)
+            .await
+            .map_err(internal("failed fetching amounts for gini coefficient"))?;
+        summary["giniCoefficient"] = json!(gini(&amounts));
+    }
+
+    if params.include_amount_stats {
+        let stats = repository::amount_stats(db(), &filter)
+            .await
+            .map_err(internal("failed fetching amount stats"))?;
+        for name in &processor_names {
+            let stats = stats.iter().find(|stats| &stats.name == name);
+            let stat = |value: fn(&repository::AmountStats) -> Option<Decimal>| {
+                stats.and_then(value).map_or(JsonValue::Null, amount)
+            };
+            summary[name]["amountStats"] = json!({
+                "min": stat(|stats| stats.min),
+                "max": stat(|stats| stats.max),
+                "mean": stat(|stats| stats.mean),
+                "median": stat(|stats| stats.median),
+                "stddev": stat(|stats| stats.stddev),
+            });
+        }
+    }
+
+    if params.include_amount_quartiles {
+        let quartiles = repository::amount_quartiles(db(), &filter)
+            .await
+            .map_err(internal("failed fetching amount quartiles"))?;
+        // tukey's fences, anything past them is an outlier on a box plot
+        let fence = Decimal::new(15, 1);
+        for name in &processor_names {
+            let quartiles = quartiles.iter().find(|quartiles| &quartiles.name == name);
+            let (q1, q2, q3) = match quartiles {
+                Some(quartiles) => (quartiles.q1, quartiles.q2, quartiles.q3),
+                None => (None, None, None),
+            };
+            let iqr = q1.zip(q3).map(|(q1, q3)| q3 - q1);
+            let stat = |value: Option<Decimal>| value.map_or(JsonValue::Null, amount);
+
+            summary[name]["amountQuartiles"] = json!({
+                "q1": stat(q1),
+                "q2": stat(q2),
+                "q3": stat(q3),
+                "iqr": stat(iqr),
+            });
+            summary[name]["outlierBounds"] = json!({
+                "lower": stat(q1.zip(iqr).map(|(q1, iqr)| (q1 - fence * iqr).round_dp(2))),
+                "upper": stat(q3.zip(iqr).map(|(q3, iqr)| (q3 + fence * iqr).round_dp(2))),
+            });
+        }
+    }
+
+    if params.include_correlation_entropy {
+        let counts = repository::correlation_id_nibbles(db(), &filter)
+            .await
+            .map_err(internal("failed fetching correlation id distribution"))?;
+        let entropy = shannon_entropy(&counts);
+        summary["correlationIdEntropy"] = json!(entropy);
+
+        // with few payments the digits can't spread out evenly, so there is nothing to flag
+        let sample: i64 = counts.iter().sum();
+        if sample >= MIN_CORRELATION_ENTROPY_SAMPLE && entropy < LOW_CORRELATION_ENTROPY {
+            summary["anomalyWarnings"] = json!([format!(
+                "correlation id entropy {:.2} is below {}, ids may be replayed or guessed",
+                entropy, LOW_CORRELATION_ENTROPY
+            )]);
+        }
+    }
+
+    if params.include_processing_efficiency {
+        let efficiencies = repository::processing_efficiency(db(), &filter)
+            .await
+            .map_err(internal("failed fetching processing efficiency"))?;
+        for name in &processor_names {
+            let efficiency = efficiencies
+                .iter()
+                .find(|efficiency| &efficiency.name == name);
+            summary[name]["processingEfficiency"] = json!({
+                "successRate": efficiency.map(|efficiency| efficiency.success_rate),
+                "avgAttemptsPerPayment":
+                    efficiency.map(|efficiency| efficiency.avg_attempts_per_payment),
+            });
+        }
+    }
+
+    if params.include_correlation_id_patterns {
+        let versions = repository::uuid_versions(db(), &filter)
+            .await
+            .map_err(internal("failed fetching uuid versions"))?;
+        for name in &processor_names {
+            let versions = versions.iter().find(|versions| &versions.name == name);
+            summary[name]["uuidVersionBreakdown"] = json!({
+                "v4": versions.map_or(0, |versions| versions.v4),
+                "v7": versions.map_or(0, |versions| versions.v7),
+                "other": versions.map_or(0, |versions| versions.other),
+            });
+        }
+    }
+
+    if params.include_time_to_first_payment {
+        let times = repository::time_to_first_payment(db(), &filter)
+            .await
+            .map_err(internal("failed fetching time to first payment"))?;
+        for name in &processor_names {
+            let time = times.iter().find(|time| &time.name == name);
+            summary[name]["timeToFirstPaymentSeconds"] = json!(time.and_then(|time| time.seconds));
+        }
+    }
+
+    if params.include_burstiness {
+        let burstiness = repository::burstiness(db(), &filter)
+            .await
+            .map_err(internal("failed fetching burstiness"))?;
+        for name in &processor_names {
+            let burstiness = burstiness
+                .iter()
+                .find(|burstiness| &burstiness.name == name);
+            summary[name]["burstinessCoefficient"] =
+                json!(burstiness.and_then(|burstiness| burstiness.coefficient));
+        }
+    }
+
+    if params.include_payment_ids {
+        let limit = params
+            .payment_ids_limit
+            .unwrap_or(DEFAULT_PAYMENT_IDS_LIMIT);
+        if !(1..=MAX_PAYMENT_IDS_LIMIT).contains(&limit) {
+            return Err(SummaryError::BadRequest("payment_ids_limit out of range"));
+        }
+
+        let payment_ids = repository::payment_ids(db(), &filter, limit, params.randomize_order)
+            .await
+            .map_err(internal("failed fetching payment ids"))?;
+        for name in &processor_names {
+            let ids = payment_ids.iter().find(|ids| &ids.name == name);
+            summary[name]["paymentIds"] = json!(ids.map_or(&[][..], |ids| &ids.ids));
+        }
+    }
+
+    if params.include_day_of_week {
+        let totals = repository::day_of_week_totals(db(), &filter)
+            .await
+            .map_err(internal("failed fetching day of week totals"))?;
+        // monday first, the way the week is laid out here
+        let mut stats = json!({});
+        for day_of_week in (1..7).chain(0..1) {
+            let totals = totals
+                .iter()
+                .find(|totals| totals.day_of_week == day_of_week);
+            stats[DAYS_OF_WEEK[day_of_week as usize]] = json!({
+                "totalAmount": amount(totals.map_or(Decimal::ZERO, |totals| totals.total_amount)),
+                "totalRequests": totals.map_or(0, |totals| totals.total_requests),
+            });
+        }
+        summary["dayOfWeekStats"] = stats;
+    }
+
+    if params.include_hour_of_day {
+        let counts = repository::hour_of_day_counts(db(), &filter)
+            .await
+            .map_err(internal("failed fetching hour of day counts"))?;
+        let mut stats = json!({});
+        for hour in 0..24 {
+            let count = counts.iter().find(|count| count.hour == hour);
+            stats[hour.to_string()] = json!({
+                "totalRequests": count.map_or(0, |count| count.total_requests),
+            });
+        }
+        summary["hourOfDayStats"] = stats;
+    }
+
+    if params.include_retry_payments {
+        let retried = repository::retried_payments(db(), &filter)
+            .await
+            .map_err(internal("failed fetching retried payments"))?;
+        for name in &processor_names {
+            let retried = retried.iter().find(|retried| &retried.name == name);
+            summary[name]["retriedPayments"] = match retried {
+                Some(retried) => json!({
+                    "count": retried.count,
+                    "percentage": 100.0 * retried.count as f64 / retried.total as f64,
+                }),
+                None => json!({ "count": 0, "percentage": 0.0 }),
+            };
+        }
+    }
+
+    if params.include_sla_breaches {
+        let threshold_secs = params
+            .sla_threshold_secs
+            .unwrap_or(DEFAULT_SLA_THRESHOLD_SECS);
+
+        let breaches =
+            repository::sla_breaches(db(), &filter, threshold_secs as f64, MAX_SLA_BREACH_IDS)
+                .await
+                .map_err(internal("failed fetching sla breaches"))?;
+        for name in &processor_names {
+            let breaches = breaches.iter().find(|breaches| &breaches.name == name);
+            summary[name]["slaBreaches"] = match breaches {
+                Some(breaches) => json!({
+                    "count": breaches.count,
+                    "percentage": 100.0 * breaches.count as f64 / breaches.total as f64,
+                    "payments": breaches.ids,
+                }),
+                None => json!({ "count": 0, "percentage": 0.0, "payments": [] }),
+            };
+        }
+    }
+
+    if params.include_velocity {
+        let window_minutes = params
+            .velocity_window_minutes
+            .unwrap_or(DEFAULT_VELOCITY_WINDOW_MINUTES);
+        if !(1..=MAX_VELOCITY_WINDOW_MINUTES).contains(&window_minutes) {
+            return Err(SummaryError::BadRequest(
+                "velocity_window_minutes out of range",
+            ));
+        }
+
+        let velocities = repository::velocity(db(), &filter, window_minutes)
+            .await
+            .map_err(internal("failed fetching payment velocity"))?;
+        for name in &processor_names {
+            let velocity = velocities.iter().find(|velocity| &velocity.name == name);
+            summary[name]["currentVelocityPerMinute"] =
+                json!(velocity.map_or(0.0, |velocity| velocity.per_minute));
+        }
+    }
+
+    if params.include_p2p_ratio {
+        let ratios = repository::high_to_low_ratio(db(), &filter, HIGH_AMOUNT_THRESHOLD)
+            .await
+            .map_err(internal("failed fetching high to low amount ratio"))?;
+        for name in &processor_names {
+            let ratio = ratios.iter().find(|ratio| &ratio.name == name);
+            summary[name]["highToLowRatio"] = json!(ratio.and_then(|ratio| ratio.ratio));
+        }
+    }
+
+    if params.include_network_stats {
+        let stats = repository::network_stats(db(), &filter)
+            .await
+            .map_err(internal("failed fetching network stats"))?;
+        for name in &processor_names {
+            let stats = stats.iter().find(|stats| &stats.name == name);
+            summary[name]["networkStats"] = json!({
+                "totalHttpRequests": stats.map_or(0, |stats| stats.total_http_requests),
+                "totalRetries": stats.map_or(0, |stats| stats.total_retries),
+                "avgRetryCount": stats.and_then(|stats| stats.avg_retry_count).unwrap_or(0.0),
+            });
+        }
+    }
+
+    if let Some(reader) = geoip_db().filter(|_| params.include_geo) {
+        let totals = repository::source_ip_totals(db(), &filter)
+            .await
+            .map_err(internal("failed fetching source ip totals"))?;
+        summary["countryBreakdown"] = country_breakdown(reader, &totals);
+    }
+
+    if let Some(reader) = geoip_db().filter(|_| params.include_time_zones) {
+        let totals = repository::source_ip_totals(db(), &filter)
+            .await
+            .map_err(internal("failed fetching source ip totals"))?;
+        summary["timezoneBreakdown"] = time_zone_breakdown(reader, &totals);
+    }
+
+    if params.compare_processors.is_some() {
+        let latencies = repository::average_latency(db(), &filter)
+            .await
+            .map_err(internal("failed fetching processor latencies"))?;
+        summary["diff"] = comparison(&summary, &processor_names, &latencies);
+    }
+
+    if params.include_processor_rank {
+        let totals: Vec<(i64, Decimal)> = processor_names
+            .iter()
+            .map(|name| {
+                (
+                    summary[name]["totalRequests"].as_i64().unwrap_or(0),
+                    decimal(&summary[name]["totalAmount"]).unwrap_or_default(),
+                )
+            })
+            .collect();
+        let by_amount: Vec<Decimal> = totals.iter().map(|&(_, amount)| amount).collect();
+
+        for (name, (rank, rank_by_amount)) in processor_names
+            .iter()
+            .zip(ranks(&totals).into_iter().zip(ranks(&by_amount)))
+        {
+            summary[name]["rank"] = json!(rank);
+            summary[name]["rankByAmount"] = json!(rank_by_amount);
+        }
+    }
+
+    // display copies next to the raw numbers, unlike output_currency_symbol which replaces them
+    if let Some(locale) = &locale {
+        let mut localized_amounts = json!({});
+        for name in &processor_names {
+            let Some(JsonValue::Object(stats)) = summary.get(name) else {
+                continue;
+            };
+            let amounts: serde_json::Map<String, JsonValue> = stats
+                .iter()
+                .filter(|(key, _)| key.to_lowercase().contains("amount"))
+                .filter_map(|(key, value)| {
+                    Some((key.clone(), json!(localized(decimal(value)?, locale))))
+                })
+                .collect();
+            localized_amounts[name] = JsonValue::Object(amounts);
+        }
+        summary["localizedAmounts"] = localized_amounts;
+    }
+
+    if params.output_currency_symbol {
+        let locale = locale.unwrap_or(Locale::pt);
+
+        for name in &processor_names {
+            let total_amount = &mut summary[name]["totalAmount"];
+            *total_amount = json!(decimal(total_amount).map(|value| localized(value, &locale)));
+        }
+        summary["warning"] = json!("amounts are strings for display only");
+    }
+
+    if time_series {
+        let granularity = params.granularity.unwrap_or(Granularity::Hour);
+        let bucket_size = granularity.resolve(from, to);
+        tracing::debug!(
+            "{:?} granularity resolved to {:?} buckets",
+            granularity,
+            bucket_size
+        );
+
+        let buckets = repository::summary_buckets(db(), &filter, bucket_size)
+            .await
+            .map_err(internal("failed fetching summary time series"))?;
+        if TimeSeries::dense_len(bucket_size, &buckets) > MAX_TIME_SERIES_BUCKETS {
+            return Err(SummaryError::BadRequest(
+                "time series spans too many buckets, pick a coarser granularity",
+            ));
+        }
+        let mut series = TimeSeries::new(&processor_names, bucket_size, &buckets);
+
+        if let Some(periods) = params.moving_average {
+            let periods = periods as usize;
+            if periods == 0 || periods > series.points.len() / 2 {
+                return Err(SummaryError::BadRequest(
+                    "moving_average must be between 1 and half the number of buckets",
+                ));
+            }
+            series.apply_moving_average(periods);
+        }
+
+        if params.include_running_total {
+            series.apply_running_total();
+        }
+
+        if params.include_momentum {
+            series.apply_momentum(bucket_size);
+        }
+
+        if params.forecast {
+            let horizon = TimeDelta::minutes(horizon_minutes as i64);
+            summary["forecast"] = series.forecast(bucket_size, horizon);
+            summary["forecastWarning"] =
+                json!("naive linear extrapolation of requests per bucket, not a prediction");
+        }
+
+        summary["timeSeries"] = if params.pivot {
+            series.to_pivoted_json()
+        } else {
+            series.to_json()
+        };
+    }
+
+    if params.deduplicate {
+        merge_aliases(&mut summary, &processor_names);
+    }
+
+    if let Some(fields) = &projection {
+        for name in &processor_keys(params) {
+            if let Some(JsonValue::Object(stats)) = summary.get_mut(name) {
+                stats.retain(|field, _| fields.contains(&field.as_str()));
+            }
+        }
+    }
+
+    if let Some(offset) = time_offset {
+        with_offset(&mut summary, offset);
+    }
+
+    if let Some(lag) = params.simulate_lag_ms {
+        let lag = lag.min(MAX_SIMULATED_LAG_MS);
+        tokio::time::sleep(Duration::from_millis(lag)).await;
+        summary["simulatedLagMs"] = json!(lag);
+    }
+
+    if params.randomize_order {
+        shuffle_keys(&mut summary);
+    }
+
+    Ok(summary)
+}
+
+fn selected_processors(
+    params: &SummaryParams,
+) -> Result<Vec<&'static ProcessorRuntime>, SummaryError> {
+    let excluded = processor_list(params.exclude_processors.as_deref()).ok_or(
+        SummaryError::BadRequest("unknown processor in exclude_processors"),
+    )?;
+    let compared = processor_list(params.compare_processors.as_deref()).ok_or(
+        SummaryError::BadRequest("unknown processor in compare_processors"),
+    )?;
+
+    if params.compare_processors.is_some()
+        && !(2..=MAX_COMPARED_PROCESSORS).contains(&compared.len())
+    {
+        return Err(SummaryError::BadRequest(
+            "compare_processors takes between 2 and 5 processors",
+        ));
+    }
+
+    Ok(external_processors()
+        .iter()
+        .filter(|p| compared.is_empty() || compared.contains(&p.name.as_str()))
+        .filter(|p| !excluded.contains(&p.name.as_str()))
+        .collect())
+}
+
+/// the filter is bound as a query parameter, so limiting its size is all there is to sanitize
+fn metadata_filter(filter: Option<&str>) -> Result<Option<JsonValue>, SummaryError> {
+    let Some(filter) = filter else {
+        return Ok(None);
+    };
+
+    if filter.len() > MAX_METADATA_FILTER_BYTES {
+        return Err(SummaryError::BadRequest("metadata_filter is too large"));
+    }
+
+    match serde_json::from_str::<JsonValue>(filter) {
+        Ok(JsonValue::Object(keys)) if keys.len() <= MAX_METADATA_FILTER_KEYS => {
+            Ok(Some(JsonValue::Object(keys)))
+        }
+        Ok(JsonValue::Object(_)) => Err(SummaryError::BadRequest(
+            "metadata_filter has too many keys",
+        )),
+        _ => Err(SummaryError::BadRequest(
+            "metadata_filter must be a json object",
+        )),
+    }
+}
+
+/// in bits, so 4 tops it for ids spread evenly over the 16 hex digits
+fn shannon_entropy(counts: &[i64]) -> f64 {
+    let total = counts.iter().sum::<i64>() as f64;
+    if total == 0.0 {
+        return 0.0;
+    }
+
+    -counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            p * p.log2()
+        })
+        .sum::<f64>()
+}
+
+/// most intervals open at once, sweeping over their starts and ends in order. intervals are
+/// half open, one ending right as another starts doesn't overlap it, and an empty one overlaps
+/// nothing at all
+fn peak_concurrency(intervals: &[(DateTime<Utc>, DateTime<Utc>)]) -> usize {
+    let mut events: Vec<(DateTime<Utc>, bool)> = intervals
+        .iter()
+        .filter(|(start, end)| start < end)
+        .flat_map(|&(start, end)| [(start, true), (end, false)])
+        .collect();
+    // false sorts first, so ends at a given instant are swept before starts
+    events.sort_unstable();
+
+    let (mut open, mut peak) = (0usize, 0usize);
+    for (_, starts) in events {
+        if starts {
+            open += 1;
+            peak = peak.max(open);
+        } else {
+            open -= 1;
+        }
+    }
+    peak
+}
+
+/// 1 for the highest, ties share a rank and the ones after skip past them, `1, 2, 2, 4`
+fn ranks<T: PartialOrd>(values: &[T]) -> Vec<usize> {
+    values
+        .iter()
+        .map(|value| 1 + values.iter().filter(|other| *other > value).count())
+        .collect()
+}
+
+/// gini coefficient of the ascending `amounts`, 0 when they are all equal or there are none
+fn gini(amounts: &[Decimal]) -> f64 {
+    let (Some(first), Some(last)) = (amounts.first(), amounts.last()) else {
+        return 0.0;
+    };
+    if first == last {
+        return 0.0;
+    }
+
+    let amounts: Vec<f64> = amounts.iter().filter_map(ToPrimitive::to_f64).collect();
+    let n = amounts.len() as f64;
+    let sum: f64 = amounts.iter().sum();
+    if sum == 0.0 {
+        return 0.0;
+    }
+
+    let weighted_sum: f64 = amounts
+        .iter()
+        .zip(1..)
+        .map(|(amount, i)| i as f64 * amount)
+        .sum();
+
+    2.0 * weighted_sum / (n * sum) - (n + 1.0) / n
+}
+
+/// dimensions the summary is grouped by, processor alone being the default
+struct GroupBy {
+    processor: bool,
+    day: bool,
+}
+
+impl GroupBy {
+    fn parse(dimensions: Option<&str>) -> Result<GroupBy, SummaryError> {
+        let Some(dimensions) = dimensions else {
+            return Ok(GroupBy {
+                processor: true,
+                day: false,
+            });
+        };
+
+        let mut group_by = GroupBy {
+            processor: false,
+            day: false,
+        };
+        for dimension in dimensions.split(',').map(str::trim) {
+            match dimension {
+                "processor" => group_by.processor = true,
+                "day" => group_by.day = true,
+                _ => return Err(SummaryError::BadRequest("unknown group_by dimension")),
+            }
+        }
+
+        Ok(group_by)
+    }
+}
+
+/// processor as the outer key and an array of days inside, or just the days across all processors
+/// only buckets with payments, every one with all processors in `processor_names`
+fn bucketed_summary(
+    buckets: &[repository::SummaryBucket],
+    processor_names: &[String],
+) -> JsonValue {
+    // rows come ordered by bucket, so each bucket's processors are next to each other
+    buckets
+        .chunk_by(|a, b| a.t == b.t)
+        .map(|buckets| {
+            let mut entry = json!({ "bucket": buckets[0].t });
+            for name in processor_names {
+                let bucket = buckets.iter().find(|bucket| &bucket.processed_by == name);
+                entry[name] = json!({
+                    "totalAmount": amount(bucket.map_or(Decimal::ZERO, |bucket| bucket.total_amount)),
+                    "totalRequests": bucket.map_or(0, |bucket| bucket.total_requests),
+                });
+            }
+            entry
+        })
+        .collect()
+}
+
+fn daily_summary(
+    days: &[repository::DailySummary],
+    processor_names: &[String],
+    by_processor: bool,
+) -> JsonValue {
+    let day = |date: NaiveDate, total_amount: Decimal, total_requests: i64| {
+        json!({
+            "date": date,
+            "totalAmount": amount(total_amount),
+            "totalRequests": total_requests,
+        })
+    };
+
+    if by_processor {
+        return processor_names
+            .iter()
+            .map(|name| {
+                let days = days
+                    .iter()
+                    .filter(|summary| &summary.name == name)
+                    .map(|summary| day(summary.date, summary.total_amount, summary.total_requests))
+                    .collect();
+                (name.clone(), days)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+    }
+
+    // rows come ordered by date, so each day's processors are next to each other
+    let days = days
+        .chunk_by(|a, b| a.date == b.date)
+        .map(|summaries| {
+            let total_amount = summaries.iter().map(|summary| summary.total_amount).sum();
+            let total_requests = summaries.iter().map(|summary| summary.total_requests).sum();
+            day(summaries[0].date, total_amount, total_requests)
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "days": days })
+}
+
+/// parses `+03:00` or `-05:30`. an unencoded `+` arrives as a space, so that counts as positive too
+fn fixed_offset(offset: &str) -> Option<FixedOffset> {
+    let (sign, offset) = match offset.strip_prefix('-') {
+        Some(offset) => (-1, offset),
+        None => (1, offset.trim_start().trim_start_matches('+')),
+    };
+    let (hours, minutes) = offset.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    if minutes >= 60 {
+        return None;
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// every timestamp in the summary is serialized as rfc 3339 by now, so they get rewritten in place
+fn with_offset(value: &mut JsonValue, offset: FixedOffset) {
+    match value {
+        JsonValue::String(text) => {
+            if let Ok(timestamp) = DateTime::parse_from_rfc3339(text) {
+                *text = timestamp
+                    .with_timezone(&offset)
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, false);
+            }
+        }
+        JsonValue::Array(values) => values
+            .iter_mut()
+            .for_each(|value| with_offset(value, offset)),
+        JsonValue::Object(values) => values
+            .values_mut()
+            .for_each(|value| with_offset(value, offset)),
+        _ => {}
+    }
+}
+
+/// parses a comma separated list of the per processor fields to keep, the rest of the summary is
+/// left alone
+fn projection(fields: Option<&str>) -> Result<Option<Vec<&str>>, SummaryError> {
+    let Some(fields) = fields else {
+        return Ok(None);
+    };
+
+    let fields: Vec<&str> = fields
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .collect();
+
+    match fields
+        .iter()
+        .find(|field| !PROJECTABLE_FIELDS.contains(field))
+    {
+        Some(unknown) => {
+            tracing::info!("unknown field {} in summary projection", unknown);
+            Err(SummaryError::BadRequest("unknown field in projection"))
+        }
+        None => Ok(Some(fields)),
+    }
+}
+
+/// an rfc 3339 timestamp polls on requested_at as it always has, anything else is a keyset
+/// cursor from an earlier `nextCursor`, and an empty one starts from the beginning of the window
+fn since_cursor(cursor: Option<&str>) -> Result<Option<Since>, SummaryError> {
+    let Some(cursor) = cursor.filter(|cursor| !cursor.is_empty()) else {
+        return Ok(None);
+    };
+
+    if let Ok(requested_at) = DateTime::parse_from_rfc3339(cursor) {
+        return Ok(Some(Since::RequestedAfter(requested_at.to_utc())));
+    }
+
+    payments::decode_cursor(cursor)
+        .map(|(processed_at, id)| Some(Since::ProcessedAfter(processed_at, id)))
+        .ok_or(SummaryError::BadRequest("invalid since_cursor"))
+}
+
+/// parses a comma separated list of correlation ids, duplicates collapsed
+fn correlation_ids(ids: Option<&str>) -> Result<Option<Vec<Uuid>>, SummaryError> {
+    let Some(ids) = ids else {
+        return Ok(None);
+    };
+
+    let mut ids = ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(Uuid::parse_str)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| SummaryError::BadRequest("correlation_ids must be uuids"))?;
+    ids.sort_unstable();
+    ids.dedup();
+
+    if ids.is_empty() {
+        return Err(SummaryError::BadRequest("correlation_ids is empty"));
+    }
+    if ids.len() > MAX_CORRELATION_IDS {
+        return Err(SummaryError::BadRequest("too many correlation_ids"));
+    }
+
+    Ok(Some(ids))
+}
+
+/// ascending amount boundaries, with an optional trailing `infinity` leaving the last bucket open
+struct HistogramBuckets {
+    thresholds: Vec<Decimal>,
+    open_ended: bool,
+}
+
+impl HistogramBuckets {
+    fn parse(boundaries: &str) -> Result<HistogramBuckets, SummaryError> {
+        let mut boundaries: Vec<&str> = boundaries.split(',').map(str::trim).collect();
+        let open_ended = boundaries
+            .last()
+            .is_some_and(|boundary| boundary.eq_ignore_ascii_case("infinity"));
+        if open_ended {
+            boundaries.pop();
+        }
+
+        let thresholds = boundaries
+            .iter()
+            .map(|boundary| Decimal::from_str(boundary))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| SummaryError::BadRequest("histogram_buckets must be decimals"))?;
+
+        let buckets = (thresholds.len() + open_ended as usize).saturating_sub(1);
+        if buckets == 0 {
+            return Err(SummaryError::BadRequest(
+                "histogram_buckets needs at least one bucket",
+            ));
+        }
+        if buckets > MAX_HISTOGRAM_BUCKETS {
+            return Err(SummaryError::BadRequest("too many histogram_buckets"));
+        }
+        if thresholds.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(SummaryError::BadRequest(
+                "histogram_buckets must be strictly ascending",
+            ));
+        }
+
+        Ok(HistogramBuckets {
+            thresholds,
+            open_ended,
+        })
+    }
+
+    /// every bucket for the processor, empty ones included
+    fn to_json(&self, name: &str, counts: &[repository::HistogramCount]) -> JsonValue {
+        let upper_bounds = self
+            .thresholds
+            .iter()
+            .skip(1)
+            .map(|bound| json!(bound))
+            .chain(self.open_ended.then(|| json!("infinity")));
+
+        self.thresholds
+            .iter()
+            .zip(upper_bounds)
+            .zip(1..)
+            .map(|((lower_bound, upper_bound), bucket)| {
+                let count = counts
+                    .iter()
+                    .find(|count| count.name == name && count.bucket == bucket)
+                    .map_or(0, |count| count.count);
+
+                json!({
+                    "lowerBound": lower_bound,
+                    "upperBound": upper_bound,
+                    "count": count,
+                })
+            })
+            .collect()
+    }
+}
+
+/// parses a comma separated list of processor names, `None` if any of them is unknown
+fn processor_list(names: Option<&str>) -> Option<Vec<&str>> {
+    let names: Vec<&str> = names
+        .into_iter()
+        .flat_map(|names| names.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    match names
+        .iter()
+        .find(|&&name| !external_processors().iter().any(|p| p.name == name))
+    {
+        Some(unknown) => {
+            tracing::info!("unknown processor {} in summary params", unknown);
+            None
+        }
+        None => Some(names),
+    }
+}
+
+/// ips the database doesn't know about are grouped under `unknown`
+fn country_breakdown(
+    reader: &maxminddb::Reader<Vec<u8>>,
+    totals: &[repository::SourceIpTotals],
+) -> JsonValue {
+    let mut countries: BTreeMap<String, (Decimal, i64)> = BTreeMap::new();
+    for source in totals {
+        let country = geoip::country(reader, source.ip).unwrap_or_else(|| "unknown".to_string());
+        let (total_amount, total_requests) = countries.entry(country).or_default();
+        *total_amount += source.total_amount;
+        *total_requests += source.total_requests;
+    }
+
+    countries
+        .into_iter()
+        .map(|(country, (total_amount, total_requests))| {
+            let totals = json!({
+                "totalAmount": amount(total_amount),
+                "totalRequests": total_requests,
+            });
+            (country, totals)
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// keyed by the utc offset each zone is at right now, so zones sharing one are counted together
+/// and ips without a known zone go under `unknown`
+fn time_zone_breakdown(
+    reader: &maxminddb::Reader<Vec<u8>>,
+    totals: &[repository::SourceIpTotals],
+) -> JsonValue {
+    let now = Utc::now().naive_utc();
+    let mut offsets: BTreeMap<String, i64> = BTreeMap::new();
+    for source in totals {
+        let offset = geoip::time_zone(reader, source.ip).map_or_else(
+            || "unknown".to_string(),
+            |zone| zone.offset_from_utc_datetime(&now).fix().to_string(),
+        );
+        *offsets.entry(offset).or_default() += source.total_requests;
+    }
+
+    offsets
+        .into_iter()
+        .map(|(offset, count)| (offset, json!({ "count": count })))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// the keys processors are under in the summary, configured processors that weren't selected
+/// are missing from it. `deduplicate` leaves aliased processors under their alias
+fn processor_keys(params: &SummaryParams) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    for processor in external_processors() {
+        let key = match params.deduplicate {
+            true => config()
+                .processor_aliases
+                .get(&processor.name)
+                .unwrap_or(&processor.name),
+            false => &processor.name,
+        };
+        if !keys.contains(key) {
+            keys.push(key.clone());
+        }
+    }
+    keys
+}
+
+/// folds the totals of aliased processors into a single entry under the alias. anything else
+/// computed per processor can't just be added up, so it doesn't survive the merge
+fn merge_aliases(summary: &mut JsonValue, processor_names: &[String]) {
+    let Some(entries) = summary.as_object_mut() else {
+        return;
+    };
+
+    let mut merged: BTreeMap<&str, (Decimal, u64)> = BTreeMap::new();
+    for name in processor_names {
+        let Some(alias) = config().processor_aliases.get(name) else {
+            continue;
+        };
+        let Some(stats) = entries.remove(name) else {
+            continue;
+        };
+
+        let (total_amount, total_requests) = merged.entry(alias).or_default();
+        *total_amount += decimal(&stats["totalAmount"]).unwrap_or_default();
+        *total_requests += stats["totalRequests"].as_u64().unwrap_or_default();
+    }
+
+    for (alias, (total_amount, total_requests)) in merged {
+        // an alias can also be the name of a processor that isn't split
+        let existing = entries.get(alias);
+        let total_amount = total_amount
+            + existing
+                .and_then(|stats| decimal(&stats["totalAmount"]))
+                .unwrap_or_default();
+        let total_requests = total_requests
+            + existing
+                .and_then(|stats| stats["totalRequests"].as_u64())
+                .unwrap_or_default();
+
+        entries.insert(
+            alias.to_string(),
+            json!({
+                "totalAmount": amount(total_amount),
+                "totalRequests": total_requests,
+            }),
+        );
+    }
+}
+
+/// spread between the compared processors, and which one had the lowest average latency
+fn comparison(
+    summary: &JsonValue,
+    processor_names: &[String],
+    latencies: &[ProcessorLatency],
+) -> JsonValue {
+    let amounts: Vec<Decimal> = processor_names
+        .iter()
+        .filter_map(|name| decimal(&summary[name]["totalAmount"]))
+        .collect();
+    let requests: Vec<u64> = processor_names
+        .iter()
+        .filter_map(|name| summary[name]["totalRequests"].as_u64())
+        .collect();
+    let spread = |values: &[Decimal]| {
+        let max = values.iter().max().copied().unwrap_or_default();
+        let min = values.iter().min().copied().unwrap_or_default();
+        max - min
+    };
+
+    let faster_processor = latencies
+        .iter()
+        .filter_map(|latency| Some((latency.name.as_str(), latency.latency_ms?)))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(name, _)| name);
+
+    json!({
+        "totalAmountDiff": amount(spread(&amounts)),
+        "totalRequestsDiff": requests.iter().max().unwrap_or(&0) - requests.iter().min().unwrap_or(&0),
+        "fasterProcessor": faster_processor,
+    })
+}
+
+/// one line per processor, streamed as they are serialized
+fn ndjson(summary: JsonValue, processor_keys: Vec<String>) -> Response {
+    let lines = stream! {
+        for key in processor_keys {
+            let Some(JsonValue::Object(stats)) = summary.get(&key) else {
+                continue;
+            };
+
+            let mut line = json!({ "processor": key });
+            for (key, value) in stats {
+                line[key] = value.clone();
+            }
+            yield Ok::<_, Infallible>(format!("{}\n", line));
+        }
+    };
+
+    (
+        [(CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+fn arrow(summary: JsonValue, processor_keys: &[String]) -> Response {
+    let stream = summary_batch(&summary, processor_keys).and_then(|batch| {
+        let mut writer = StreamWriter::try_new(Vec::new(), &batch.schema())?;
+        writer.write(&batch)?;
+        writer.into_inner()
+    });
+
+    match stream {
+        Ok(body) => (
+            [(CONTENT_TYPE, "application/vnd.apache.arrow.stream")],
+            body,
+        )
+            .into_response(),
+        Err(error) => {
+            tracing::error!("failed serializing summary to arrow, {}", error);
+            ApiError::InternalError("failed serializing summary".to_string()).into_response()
+        }
+    }
+}
+
+/// the arrow schema is embedded by the writer, field names and types also go in as plain key
+/// values for readers that don't understand it
+fn parquet(summary: JsonValue, processor_keys: &[String]) -> Response {
+    let file = summary_batch(&summary, processor_keys)
+        .map_err(ParquetError::from)
+        .and_then(|batch| {
+            let fields: Vec<KeyValue> = batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| {
+                    let name = format!("field.{}", field.name());
+                    KeyValue::new(name, field.data_type().to_string())
+                })
+                .collect();
+            let properties = WriterProperties::builder()
+                .set_key_value_metadata(Some(fields))
+                .build();
+
+            let mut writer = ArrowWriter::try_new(Vec::new(), batch.schema(), Some(properties))?;
+            writer.write(&batch)?;
+            writer.into_inner()
+        });
+
+    match file {
+        Ok(body) => (
+            [
+                (CONTENT_TYPE, "application/octet-stream"),
+                (
+                    CONTENT_DISPOSITION,
+                    "attachment; filename=\"summary.parquet\"",
+                ),
+            ],
+            body,
+        )
+            .into_response(),
+        Err(error) => {
+            tracing::error!("failed serializing summary to parquet, {}", error);
+            ApiError::InternalError("failed serializing summary".to_string()).into_response()
+        }
+    }
+}
+
+/// one row per processor, same as [`ndjson`], with amounts as decimals at their widest scale
+fn summary_batch<'a>(
+    summary: &JsonValue,
+    processor_keys: &'a [String],
+) -> Result<RecordBatch, ArrowError> {
+    let rows: Vec<(&'a str, Option<Decimal>, Option<i64>)> = processor_keys
+        .iter()
+        .filter_map(|key| {
+            let stats = summary.get(key)?;
+            let total_requests = stats["totalRequests"].to_string().parse().ok();
+            Some((key.as_str(), decimal(&stats["totalAmount"]), total_requests))
+        })
+        .collect();
+
+    let scale = rows
+        .iter()
+        .filter_map(|(_, total_amount, _)| total_amount.map(|amount| amount.scale()))
+        .max()
+        .unwrap_or(2);
+    let total_amounts = rows.iter().map(|(_, total_amount, _)| {
+        total_amount.map(|mut amount| {
+            amount.rescale(scale);
+            amount.mantissa()
+        })
+    });
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("processor", DataType::Utf8, false),
+        Field::new("totalAmount", DataType::Decimal128(38, scale as i8), true),
+        Field::new("totalRequests", DataType::Int64, true),
+    ]));
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|row| row.0))),
+        Arc::new(
+            Decimal128Array::from_iter(total_amounts).with_precision_and_scale(38, scale as i8)?,
+        ),
+        Arc::new(Int64Array::from_iter(rows.iter().map(|row| row.2))),
+    ];
+
+    RecordBatch::try_new(schema, columns)
+}
+
+/// one insert per processor, literals only so the script runs on any database without binds
+fn sql(summary: JsonValue, params: &SummaryParams) -> Response {
+    // last_n resolves the window itself, otherwise it's whatever the client asked for
+    let bound = |key: &str, requested: Option<DateTime<Utc>>| match summary.get(key) {
+        Some(JsonValue::String(time)) => sql_string(time),
+        _ => requested.map_or("null".to_string(), |time| {
+            sql_string(&time.to_rfc3339_opts(SecondsFormat::Micros, true))
+        }),
+    };
+    let from = bound("from", params.from);
+    let to = bound("to", params.to);
+
+    let statements: String = processor_keys(params)
+        .iter()
+        .filter_map(|key| {
+            let stats = summary.get(key)?;
+            let total_amount = decimal(&stats["totalAmount"]).map_or("null".to_string(), |amount| amount.to_string());
+            let total_requests = stats["totalRequests"]
+                .to_string()
+                .parse::<i64>()
+                .map_or("null".to_string(), |requests| requests.to_string());
+            Some(format!(
+                "insert into payment_summary (processor, total_amount, total_requests, from_time, to_time) values ({}, {}, {}, {}, {});",
+                sql_string(key),
+                total_amount,
+                total_requests,
+                from,
+                to
+            ))
+        })
+        .collect();
+
+    let options = FormatOptions {
+        uppercase: Some(true),
+        ..FormatOptions::default()
+    };
+    let body = sqlformat::format(&statements, &QueryParams::None, &options);
+
+    ([(CONTENT_TYPE, "application/sql")], body).into_response()
+}
+
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// posts the summary in the background, signed the same way as a json response
+fn export(summary: JsonValue, url: Url, nonce: Option<String>) -> Response {
+    let job_id = Uuid::new_v4();
+    let accepted = json!({ "jobId": job_id, "webhookUrl": url.as_str() });
+
+    tokio::spawn(async move {
+        let body = summary.to_string();
+        let mut request = http_client()
+            .post(url.clone())
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(signature) = signature::sign(body.as_bytes(), nonce.as_deref()) {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        if let Some(nonce) = nonce {
+            request = request.header(NONCE_HEADER, nonce);
+        }
+
+        match request.body(body).send().await {
+            Ok(response) if response.status().is_success() => {
+                tracing::info!("exported summary {} to {}", job_id, url);
+            }
+            Ok(response) => {
+                tracing::error!(
+                    "failed exporting summary {} to {}, status {}",
+                    job_id,
+                    url,
+                    response.status()
+                );
+            }
+            Err(error) => {
+                tracing::error!("failed exporting summary {} to {}, {}", job_id, url, error);
+            }
+        }
+    });
+
+    (StatusCode::ACCEPTED, Json(accepted)).into_response()
+}
+
+/// the query string sans the params that don't change what gets built, sorted so that reordering
+/// them still hits the same entry
+fn cache_key(query: &str) -> String {
+    let mut params: Vec<&str> = query
+        .split('&')
+        .filter(|param| {
+            let name = param.split('=').next().unwrap_or_default();
+            !name.is_empty() && name != "nonce" && name != "recompute"
+        })
+        .collect();
+    params.sort_unstable();
+    params.join("&")
+}
+
+fn yaml(summary: JsonValue) -> Response {
+    match serde_yaml::to_string(&yaml_value(summary, false)) {
+        Ok(body) => ([(CONTENT_TYPE, "application/yaml")], body).into_response(),
+        Err(error) => {
+            tracing::error!("failed serializing summary to yaml, {}", error);
+            ApiError::InternalError("failed serializing summary".to_string()).into_response()
+        }
+    }
+}
+
+/// arbitrary precision numbers only survive json serializers, and yaml parsers read decimals as
+/// floats, so amounts and fractional numbers go out as strings while counts stay integers
+fn yaml_value(value: JsonValue, is_amount: bool) -> serde_yaml::Value {
+    match value {
+        JsonValue::Null => serde_yaml::Value::Null,
+        JsonValue::Bool(value) => serde_yaml::Value::Bool(value),
+        JsonValue::Number(number) => {
+            let number = number.to_string();
+            match number.parse::<i64>() {
+                Ok(integer) if !is_amount => serde_yaml::Value::Number(integer.into()),
+                _ => serde_yaml::Value::String(number),
+            }
+        }
+        JsonValue::String(value) => serde_yaml::Value::String(value),
+        JsonValue::Array(values) => values
+            .into_iter()
+            .map(|value| yaml_value(value, is_amount))
+            .collect(),
+        JsonValue::Object(values) => serde_yaml::Value::Mapping(
+            values
+                .into_iter()
+                .map(|(key, value)| {
+                    let is_amount = key.to_lowercase().contains("amount");
+                    (serde_yaml::Value::String(key), yaml_value(value, is_amount))
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn signed(summary: &JsonValue, nonce: Option<&str>) -> Response {
+    let body = summary.to_string();
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    if let Some(signature) = signature::sign(body.as_bytes(), nonce) {
+        headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+    }
+
+    if let Some(nonce) = nonce.and_then(|nonce| HeaderValue::from_str(nonce).ok()) {
+        headers.insert(NONCE_HEADER, nonce);
+    }
+
+    (StatusCode::OK, headers, body).into_response()
+}
+
+/// zeroes the last octet of ipv4 addresses, and the last group of ipv6 ones
+fn mask_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(ip) => {
+            let mut segments = ip.segments();
+            segments[7] = 0;
+            IpAddr::V6(Ipv6Addr::from(segments))
+        }
+    }
+}
+
+/// so the key order can't hint at which processor got traffic first
+fn shuffle_keys(summary: &mut JsonValue) {
+    let Some(object) = summary.as_object_mut() else {
+        return;
+    };
+
+    let mut entries: Vec<(String, JsonValue)> = std::mem::take(object).into_iter().collect();
+    match config().privacy_shuffle_seed {
+        Some(seed) => entries.shuffle(&mut StdRng::seed_from_u64(seed)),
+        None => entries.shuffle(&mut rand::rng()),
+    }
+    object.extend(entries);
+}
+
+/// decimals serialize as strings by default, the summary reports amounts as json numbers
+pub fn amount(value: Decimal) -> JsonValue {
+    serde_json::Number::from_str(&value.to_string())
+        .map(JsonValue::Number)
+        .unwrap_or(JsonValue::Null)
+}
+
+fn decimal(value: &JsonValue) -> Option<Decimal> {
+    match value {
+        JsonValue::Number(number) => Decimal::from_str(&number.to_string()).ok(),
+        JsonValue::String(string) => Decimal::from_str(string).ok(),
+        _ => None,
+    }
+}
+
+/// accepts both bare languages and regional tags, `pt-BR` is what cldr calls plain `pt`
+fn resolve_locale(name: &str) -> Option<Locale> {
+    Locale::from_name(name)
+        .or_else(|_| Locale::from_name(name.split('-').next().unwrap_or(name)))
+        .ok()
+}
+
+/// payments are always in reais, the locale only drives the separators
+fn localized(value: Decimal, locale: &Locale) -> String {
+    let value = value.round_dp(2);
+    let integer = value.trunc().to_i64().unwrap_or(i64::MAX);
+    let cents = ((value.fract() * Decimal::ONE_HUNDRED).abs())
+        .to_i64()
+        .unwrap_or(0);
+
+    format!(
+        "R$ {}{}{:02}",
+        integer.to_formatted_string(locale),
+        locale.decimal(),
+        cents
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{Int64Array, StringArray},
+        ipc::reader::StreamReader,
+        record_batch::RecordBatch,
+    };
+    use axum::{
+        body::{Body, Bytes},
+        http::{Request, StatusCode},
+    };
+    use chrono::{DateTime, Utc};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use rust_decimal::Decimal;
+    use serde_json::json;
+    use std::{net::SocketAddr, str::FromStr};
+
+    use uuid::Uuid;
+
+    use super::PROJECTABLE_FIELDS;
+    use crate::{
+        db, repository,
+        test_support::{self, processed_payment, query_time, window},
+    };
+
+    const PEER: &str = "10.0.1.1:4000";
+
+    async fn get(uri: String) -> (StatusCode, Bytes) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = test_support::send(request, PEER.parse::<SocketAddr>().unwrap()).await;
+        (response.status(), test_support::body(response).await)
+    }
+
+    fn window_query(from: DateTime<Utc>, to: DateTime<Utc>) -> String {
+        format!("from={}&to={}", query_time(from), query_time(to))
+    }
+
+    /// `default` and `fallback` are both aliased to `proc` in the test config
+    async fn deduplicated(format: &str) -> (StatusCode, Bytes) {
+        let (from, to) = window();
+        processed_payment("default", Decimal::new(1000, 2), from).await;
+        processed_payment("fallback", Decimal::new(550, 2), from).await;
+
+        get(format!(
+            "/payments-summary?{}&deduplicate=true&format={}",
+            window_query(from, to),
+            format
+        ))
+        .await
+    }
+
+    fn assert_merged(batch: &RecordBatch) {
+        let processors = batch
+            .column_by_name("processor")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        let requests = batch
+            .column_by_name("totalRequests")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(processors.value(0), "proc");
+        assert_eq!(requests.value(0), 2);
+    }
+
+    #[test]
+    fn deduplicate_merges_aliases_in_json() {
+        test_support::run(async {
+            let (status, body) = deduplicated("json").await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(summary["proc"]["totalRequests"], 2);
+            assert_eq!(
+                Decimal::from_str(&summary["proc"]["totalAmount"].to_string()).unwrap(),
+                Decimal::new(1550, 2)
+            );
+            assert!(summary.get("default").is_none());
+            assert!(summary.get("fallback").is_none());
+        })
+    }
+
+    #[test]
+    fn deduplicate_merges_aliases_in_ndjson() {
+        test_support::run(async {
+            let (status, body) = deduplicated("ndjson").await;
+            let lines: Vec<serde_json::Value> = String::from_utf8(body.to_vec())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(lines.len(), 1);
+            assert_eq!(lines[0]["processor"], "proc");
+            assert_eq!(lines[0]["totalRequests"], 2);
+        })
+    }
+
+    #[test]
+    fn deduplicate_merges_aliases_in_sql() {
+        test_support::run(async {
+            let (status, body) = deduplicated("sql").await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body.matches("INSERT INTO").count(), 1);
+            assert!(body.contains("'proc'"));
+            assert!(body.contains("15.5"));
+        })
+    }
+
+    #[test]
+    fn deduplicate_merges_aliases_in_arrow() {
+        test_support::run(async {
+            let (status, body) = deduplicated("arrow").await;
+            let batches: Vec<RecordBatch> = StreamReader::try_new(&body[..], None)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(batches.len(), 1);
+            assert_merged(&batches[0]);
+        })
+    }
+
+    #[test]
+    fn deduplicate_merges_aliases_in_parquet() {
+        test_support::run(async {
+            let (status, body) = deduplicated("parquet").await;
+            let batches: Vec<RecordBatch> = ParquetRecordBatchReaderBuilder::try_new(body)
+                .unwrap()
+                .build()
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(batches.len(), 1);
+            assert_merged(&batches[0]);
+        })
+    }
+
+    #[test]
+    fn alert_if_zero_fails_every_shape_of_an_empty_window() {
+        test_support::run(async {
+            let (from, to) = window();
+            for shape in ["", "&rollup=true", "&group_by=day", "&bucket=hour"] {
+                let (status, body) = get(format!(
+                    "/payments-summary?{}&alert_if_zero=true{}",
+                    window_query(from, to),
+                    shape
+                ))
+                .await;
+                let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+                assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", shape);
+                assert_eq!(error["code"], "no_payments");
+            }
+        })
+    }
+
+    #[test]
+    fn alert_if_zero_passes_every_shape_of_a_window_with_payments() {
+        test_support::run(async {
+            let (from, to) = window();
+            processed_payment("default", Decimal::TEN, from).await;
+            for shape in ["", "&rollup=true", "&group_by=day", "&bucket=hour"] {
+                let (status, _) = get(format!(
+                    "/payments-summary?{}&alert_if_zero=true{}",
+                    window_query(from, to),
+                    shape
+                ))
+                .await;
+
+                assert_eq!(status, StatusCode::OK, "{}", shape);
+            }
+        })
+    }
+
+    #[test]
+    fn alert_if_zero_does_not_count_cancelled_payments() {
+        test_support::run(async {
+            let (from, to) = window();
+            let id = Uuid::new_v4();
+            repository::insert(db(), id, Decimal::TEN, from, None, None)
+                .await
+                .unwrap();
+            repository::cancel(db(), id, "test").await.unwrap();
+
+            let (status, _) = get(format!(
+                "/payments-summary?{}&alert_if_zero=true&include_cancelled=true",
+                window_query(from, to)
+            ))
+            .await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        })
+    }
+
+    #[test]
+    fn randomize_order_shuffles_raw_payments_and_payment_ids() {
+        test_support::run(async {
+            let (from, to) = window();
+            let mut ids = Vec::new();
+            for second in 0..30 {
+                let requested_at = from + chrono::TimeDelta::seconds(second);
+                ids.push(processed_payment("default", Decimal::ONE, requested_at).await);
+            }
+
+            let (status, body) = get(format!(
+                "/payments-summary?{}&include_raw_payments=true&include_payment_ids=true\
+                 &exclude_processors=fallback&randomize_order=true",
+                window_query(from, to)
+            ))
+            .await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let raw: Vec<Uuid> = summary["payments"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|payment| payment["correlationId"].as_str().unwrap().parse().unwrap())
+                .collect();
+            let handled: Vec<Uuid> = summary["default"]["paymentIds"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|id| id.as_str().unwrap().parse().unwrap())
+                .collect();
+
+            assert_eq!(status, StatusCode::OK);
+            let newest_first: Vec<Uuid> = ids.iter().rev().copied().collect();
+            assert_ne!(raw, newest_first);
+            assert_ne!(handled, ids);
+            let (mut raw, mut handled) = (raw, handled);
+            raw.sort();
+            handled.sort();
+            ids.sort();
+            assert_eq!(raw, ids);
+            assert_eq!(handled, ids);
+        })
+    }
+
+    #[test]
+    fn include_processor_config_embeds_each_weight() {
+        test_support::run(async {
+            let (from, to) = window();
+            let (status, body) = get(format!(
+                "/payments-summary?{}&include_processor_config=true",
+                window_query(from, to)
+            ))
+            .await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(summary["default"]["weight"], 1);
+            assert_eq!(summary["fallback"]["weight"], 0);
+            assert!(summary["default"]["endpoint"].is_string());
+
+            let (status, body) = get(format!(
+                "/payments-summary?{}&include_processor_config=true&projection=weight",
+                window_query(from, to)
+            ))
+            .await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(summary["default"], json!({ "weight": 1 }));
+        })
+    }
+
+    #[test]
+    fn since_cursor_picks_up_payments_processed_after_newer_ones() {
+        test_support::run(async {
+            let (from, to) = window();
+            let late = Uuid::new_v4();
+            repository::insert(db(), late, Decimal::ONE, from, None, None)
+                .await
+                .unwrap();
+            processed_payment(
+                "default",
+                Decimal::TEN,
+                from + chrono::TimeDelta::seconds(30),
+            )
+            .await;
+
+            let poll = |cursor: String| {
+                get(format!(
+                    "/payments-summary?{}&since_cursor={}",
+                    window_query(from, to),
+                    cursor
+                ))
+            };
+            let summary = |body: Bytes| serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+
+            let (status, first) = poll(String::new()).await;
+            let first = summary(first);
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(first["default"]["totalRequests"], 1);
+            assert_eq!(first["default"]["totalAmount"].as_f64(), Some(10.0));
+
+            // the cursor only narrows the totals, the side queries still see the pending one
+            let (_, pending) = get(format!(
+                "/payments-summary?{}&since_cursor={}&watermark=true",
+                window_query(from, to),
+                first["nextCursor"].as_str().unwrap()
+            ))
+            .await;
+            let pending = summary(pending);
+            assert_eq!(pending["default"]["totalRequests"], 0);
+            assert_eq!(
+                pending["processingWatermark"]
+                    .as_str()
+                    .map(|at| at.parse::<DateTime<Utc>>().unwrap()),
+                Some(from)
+            );
+
+            repository::set_processed_by(db(), late, "default")
+                .await
+                .unwrap();
+            let (_, second) = poll(first["nextCursor"].as_str().unwrap().to_string()).await;
+            let second = summary(second);
+            assert_eq!(second["default"]["totalRequests"], 1);
+            assert_eq!(second["default"]["totalAmount"].as_f64(), Some(1.0));
+
+            let cursor = second["nextCursor"].as_str().unwrap().to_string();
+            let (_, third) = poll(cursor.clone()).await;
+            let third = summary(third);
+            assert_eq!(third["default"]["totalRequests"], 0);
+            assert_eq!(third["nextCursor"], cursor);
+
+            let (status, _) = poll("not-a-cursor".to_string()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        })
+    }
+
+    #[test]
+    fn since_cursor_still_takes_a_requested_at_timestamp() {
+        test_support::run(async {
+            let (from, to) = window();
+            let newest = from + chrono::TimeDelta::seconds(30);
+            processed_payment("default", Decimal::ONE, from).await;
+            processed_payment("default", Decimal::TEN, newest).await;
+
+            let poll = |since: DateTime<Utc>| {
+                get(format!(
+                    "/payments-summary?{}&since_cursor={}",
+                    window_query(from, to),
+                    query_time(since)
+                ))
+            };
+
+            let (status, body) = poll(from).await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(summary["default"]["totalRequests"], 1);
+            assert_eq!(summary["default"]["totalAmount"].as_f64(), Some(10.0));
+            let next: DateTime<Utc> =
+                serde_json::from_value(summary["nextCursor"].clone()).unwrap();
+            assert_eq!(next, newest);
+
+            let (_, body) = poll(newest).await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(summary["default"]["totalRequests"], 0);
+            let next: DateTime<Utc> =
+                serde_json::from_value(summary["nextCursor"].clone()).unwrap();
+            assert_eq!(next, newest);
+        })
+    }
+
+    #[test]
+    fn time_series_past_the_bucket_cap_is_a_bad_request() {
+        test_support::run(async {
+            let (from, _) = window();
+            let to = from + chrono::TimeDelta::days(30);
+            processed_payment("default", Decimal::ONE, from).await;
+            processed_payment("default", Decimal::ONE, to - chrono::TimeDelta::minutes(1)).await;
+
+            let series = |granularity: &str| {
+                get(format!(
+                    "/payments-summary?{}&granularity={}",
+                    window_query(from, to),
+                    granularity
+                ))
+            };
+
+            let (status, _) = series("minute").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+
+            let (status, body) = series("hour").await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(status, StatusCode::OK);
+            // hour buckets starting wherever the window's first minute falls
+            let points = summary["timeSeries"].as_array().unwrap().len();
+            assert!(
+                (30 * 24..=30 * 24 + 1).contains(&points),
+                "{} points",
+                points
+            );
+        })
+    }
+
+    #[test]
+    fn hour_and_day_of_week_are_utc_whatever_the_session_time_zone() {
+        test_support::run(async {
+            // the test pool runs in asia/tokyo, where this is already the next morning
+            let (from, _) = window();
+            let late = from.date_naive().and_hms_opt(23, 59, 30).unwrap().and_utc();
+            processed_payment("default", Decimal::ONE, late).await;
+
+            let (status, body) = get(format!(
+                "/payments-summary?{}&include_hour_of_day=true&include_day_of_week=true",
+                window_query(late, late + chrono::TimeDelta::minutes(1))
+            ))
+            .await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(summary["hourOfDayStats"]["23"]["totalRequests"], 1);
+            assert_eq!(summary["hourOfDayStats"]["8"]["totalRequests"], 0);
+            let day = late.format("%A").to_string();
+            assert_eq!(summary["dayOfWeekStats"][&day]["totalRequests"], 1);
+        })
+    }
+
+    #[test]
+    fn invalid_options_are_rejected_before_alert_if_zero_counts_anything() {
+        test_support::run(async {
+            let (from, to) = window();
+
+            for option in [
+                "locale=xx-XX",
+                "sample_rate=NaN",
+                "explain=true",
+                "forecast=true",
+                "time_series=true&forecast=true&forecast_horizon_minutes=0",
+                "include_running_total=true",
+                "include_momentum=true",
+                "simulate_lag_ms=10",
+            ] {
+                let (status, _) = get(format!(
+                    "/payments-summary?{}&alert_if_zero=true&{}",
+                    window_query(from, to),
+                    option
+                ))
+                .await;
+                assert_eq!(status, StatusCode::BAD_REQUEST, "{}", option);
+            }
+        })
+    }
+
+    #[test]
+    fn admin_only_options_are_unauthorized_with_a_code() {
+        test_support::run(async {
+            let (from, to) = window();
+            for option in [
+                "recompute=true",
+                "include_raw_payments=true&raw_payments_limit=500",
+                "export_to_webhook=true&webhook_url=http://localhost/hook",
+            ] {
+                let (status, body) = get(format!(
+                    "/payments-summary?{}&{}",
+                    window_query(from, to),
+                    option
+                ))
+                .await;
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+                assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", option);
+                assert_eq!(body["code"], "unauthorized", "{}", option);
+            }
+        })
+    }
+
+    /// every per processor option at once, so a field written without being made projectable
+    /// shows up here
+    #[test]
+    fn every_processor_field_is_projectable() {
+        test_support::run(async {
+            let (from, to) = window();
+            for second in 0..5 {
+                let requested_at = from + chrono::TimeDelta::seconds(second);
+                processed_payment("default", Decimal::new(1000 + second, 2), requested_at).await;
+            }
+
+            let options = [
+                "include_processor_config",
+                "include_histogram",
+                "include_network_stats",
+                "include_amount_stats",
+                "include_p2p_ratio",
+                "include_velocity",
+                "include_processing_efficiency",
+                "include_failure_modes",
+                "include_concurrent_load",
+                "include_processor_rank",
+                "include_time_to_first_payment",
+                "include_burstiness",
+                "include_payment_ids",
+                "include_retry_payments",
+                "include_amount_quartiles",
+                "include_sla_breaches",
+                "include_correlation_id_patterns",
+            ];
+            let (status, body) = get(format!(
+                "/payments-summary?{}&{}",
+                window_query(from, to),
+                options.map(|option| format!("{}=true", option)).join("&")
+            ))
+            .await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(status, StatusCode::OK);
+
+            let fields: Vec<&String> = summary["default"].as_object().unwrap().keys().collect();
+            assert!(fields.iter().any(|field| *field == "sloCompliance"));
+            for field in fields {
+                assert!(
+                    PROJECTABLE_FIELDS.contains(&field.as_str()),
+                    "{} is written but not projectable",
+                    field
+                );
+            }
+
+            let (status, body) = get(format!(
+                "/payments-summary?{}&projection=sloCompliance",
+                window_query(from, to)
+            ))
+            .await;
+            let summary: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(status, StatusCode::OK);
+            assert!(summary["default"]["sloCompliance"].is_number());
+        })
+    }
+}
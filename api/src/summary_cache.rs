@@ -0,0 +1,51 @@
+use sqlx::types::JsonValue;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const MAX_CACHED_SUMMARIES: usize = 1_000;
+
+/// recently built summaries keyed by their query, a zero ttl disables it
+#[derive(Debug)]
+pub struct SummaryCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, JsonValue)>>,
+}
+
+impl SummaryCache {
+    pub fn new(ttl: Duration) -> Self {
+        SummaryCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<JsonValue> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(cached_at, _)| cached_at.elapsed() < self.ttl)
+            .map(|(_, summary)| summary.clone())
+    }
+
+    /// expired entries are pruned first, and nothing is cached while the cache is still full
+    pub fn put(&self, key: String, summary: &JsonValue) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED_SUMMARIES {
+            entries.retain(|_, (cached_at, _)| cached_at.elapsed() < self.ttl);
+        }
+        if entries.len() < MAX_CACHED_SUMMARIES || entries.contains_key(&key) {
+            entries.insert(key, (Instant::now(), summary.clone()));
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! one runtime, one set of statics and two mock processors shared by every test, so tests take
+//! turns through `run` instead of running in parallel
+
+use axum::{
+    Router,
+    body::{Body, Bytes, to_bytes},
+    extract::{ConnectInfo, State},
+    http::{Request, StatusCode, header::AUTHORIZATION},
+    response::Response,
+    routing::post,
+};
+use chrono::{DateTime, SecondsFormat, TimeDelta, TimeZone, Utc};
+use rand::Rng;
+use rust_decimal::Decimal;
+use serde_json::json;
+use sqlx::{Executor, postgres::PgPoolOptions};
+use std::{
+    env::var,
+    net::SocketAddr,
+    sync::{
+        Mutex, OnceLock, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::{Config, DB, db, in_flight, init, processor::Payment, repository, router, sender};
+
+pub const ADMIN_TOKEN: &str = "test-token";
+pub const RATE_PER_SECOND: u32 = 1;
+pub const BURST_SIZE: u32 = 2;
+pub const RETRY_BASE_MILLIS: u64 = 20;
+pub const RETRY_CAP_MILLIS: u64 = 1_000;
+pub const MAX_WAIT_MILLIS: usize = 5;
+/// small enough for a test to fill
+pub const QUEUE_CAPACITY: usize = 64;
+/// never resolved, so it stands in for a private host the operator trusts
+pub const CALLBACK_ALLOWED_HOST: &str = "callbacks.internal";
+
+/// weight 1, every payment goes here first
+pub static DEFAULT: MockProcessor = MockProcessor::new();
+/// weight 0, only reached when `DEFAULT`'s circuit is open or by racing
+pub static FALLBACK: MockProcessor = MockProcessor::new();
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+static TURN: Mutex<()> = Mutex::new(());
+
+/// answers every payment with a 200 after `delay`, or a 500 while it has failures left
+pub struct MockProcessor {
+    delay_millis: AtomicU64,
+    failures_left: AtomicUsize,
+    in_flight: AtomicUsize,
+    peak_in_flight: AtomicUsize,
+    received: Mutex<Vec<Instant>>,
+}
+
+impl MockProcessor {
+    const fn new() -> Self {
+        MockProcessor {
+            delay_millis: AtomicU64::new(0),
+            failures_left: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            peak_in_flight: AtomicUsize::new(0),
+            received: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn reset(&self) {
+        self.delay_millis.store(0, Ordering::SeqCst);
+        self.failures_left.store(0, Ordering::SeqCst);
+        self.peak_in_flight.store(0, Ordering::SeqCst);
+        self.received.lock().unwrap().clear();
+    }
+
+    pub fn set_delay(&self, delay: Duration) {
+        self.delay_millis
+            .store(delay.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// most requests it was answering at once since the test started
+    pub fn peak_in_flight(&self) -> usize {
+        self.peak_in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn fail_next(&self, failures: usize) {
+        self.failures_left.store(failures, Ordering::SeqCst);
+    }
+
+    /// when each request came in, in order
+    pub fn received(&self) -> Vec<Instant> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+async fn handle(State(mock): State<&'static MockProcessor>) -> StatusCode {
+    mock.received.lock().unwrap().push(Instant::now());
+    let in_flight = mock.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    mock.peak_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+    // the request may be dropped mid delay, e.g. when it loses a race
+    let _answered = Answered(mock);
+
+    let failed = mock
+        .failures_left
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+            left.checked_sub(1)
+        })
+        .is_ok();
+    let delay = mock.delay_millis.load(Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(delay)).await;
+
+    match failed {
+        true => StatusCode::INTERNAL_SERVER_ERROR,
+        false => StatusCode::OK,
+    }
+}
+
+struct Answered(&'static MockProcessor);
+
+impl Drop for Answered {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// the mock's endpoint
+async fn serve(mock: &'static MockProcessor) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}/payments", listener.local_addr().unwrap());
+    let app = Router::new()
+        .route("/payments", post(handle))
+        .with_state(mock);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    endpoint
+}
+
+fn start() -> Runtime {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        let config: Config = serde_json::from_value(json!({
+            "listen_port": 0,
+            "database_url": var("DATABASE_URL").unwrap(),
+            "log_level": "Warn",
+            "max_in_flight": 16,
+            "max_wait_millis": MAX_WAIT_MILLIS,
+            "queue_capacity": QUEUE_CAPACITY,
+            "admin_token": ADMIN_TOKEN,
+            "retry_base_millis": RETRY_BASE_MILLIS,
+            "retry_cap_millis": RETRY_CAP_MILLIS,
+            "failure_threshold": 1_000,
+            "rate_per_second": RATE_PER_SECOND,
+            "burst_size": BURST_SIZE,
+            "callback_allowed_hosts": [CALLBACK_ALLOWED_HOST],
+            "processor_aliases": { "default": "proc", "fallback": "proc" },
+            "external_processors": [
+                {
+                    "name": "default",
+                    "endpoint": serve(&DEFAULT).await,
+                    "weight": 1,
+                    "slo_max_latency_ms": 1_000,
+                },
+                { "name": "fallback", "endpoint": serve(&FALLBACK).await, "weight": 0 },
+            ],
+        }))
+        .unwrap();
+
+        // anything that leans on the session time zone instead of utc shows up in the tests
+        let db = PgPoolOptions::new()
+            .after_connect(|conn, _| {
+                Box::pin(async move {
+                    conn.execute("set time zone 'Asia/Tokyo'").await?;
+                    Ok(())
+                })
+            })
+            .connect(&config.database_url)
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&db).await.unwrap();
+        DB.set(db).unwrap();
+
+        let mut processor = init(config);
+        tokio::spawn(async move { processor.run_forever(CancellationToken::new()).await });
+    });
+
+    runtime
+}
+
+/// runs `test` on the shared runtime once every test before it is done
+pub fn run<F: Future>(test: F) -> F::Output {
+    let _turn = TURN.lock().unwrap_or_else(PoisonError::into_inner);
+    let runtime = RUNTIME.get_or_init(start);
+    runtime.block_on(async {
+        settle().await;
+        DEFAULT.reset();
+        FALLBACK.reset();
+        test.await
+    })
+}
+
+/// waits out whatever payments an earlier test left queued or in flight
+async fn settle() {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !sender().is_empty() || in_flight() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("payments from an earlier test never settled");
+}
+
+/// through the whole router, as if `peer` had connected
+pub async fn send(mut request: Request<Body>, peer: SocketAddr) -> Response {
+    request.extensions_mut().insert(ConnectInfo(peer));
+    router().oneshot(request).await.unwrap()
+}
+
+pub async fn body(response: Response) -> Bytes {
+    to_bytes(response.into_body(), usize::MAX).await.unwrap()
+}
+
+pub async fn text(response: Response) -> String {
+    String::from_utf8(body(response).await.to_vec()).unwrap()
+}
+
+/// a minute somewhere in the nineties no other test has payments in, `from` and `to` as
+/// they go in a query string
+pub fn window() -> (DateTime<Utc>, DateTime<Utc>) {
+    let minutes = rand::rng().random_range(0..5_000_000);
+    let from = Utc.with_ymd_and_hms(1990, 1, 1, 0, 0, 0).unwrap() + TimeDelta::minutes(minutes);
+    (from, from + TimeDelta::minutes(1))
+}
+
+pub fn query_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// straight into the log, already processed by `processor`
+pub async fn processed_payment(
+    processor: &str,
+    amount: Decimal,
+    requested_at: DateTime<Utc>,
+) -> Uuid {
+    let id = Uuid::new_v4();
+    repository::insert(db(), id, amount, requested_at, None, None)
+        .await
+        .unwrap();
+    repository::set_processed_by(db(), id, processor)
+        .await
+        .unwrap();
+    id
+}
+
+/// a fresh payment of 10, as the api would have queued it
+pub fn payment() -> Payment {
+    Payment {
+        correlation_id: Uuid::new_v4(),
+        amount: Decimal::TEN,
+        requested_at: Utc::now(),
+        source_ip: None,
+        timeout_secs: None,
+        requeued: false,
+        callback_url: None,
+    }
+}
+
+pub async fn patch_max_in_flight(value: usize) -> StatusCode {
+    let request = Request::patch("/config/max-in-flight")
+        .header(AUTHORIZATION, format!("Bearer {}", ADMIN_TOKEN))
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "value": value }).to_string()))
+        .unwrap();
+    send(request, "10.0.3.1:4000".parse().unwrap())
+        .await
+        .status()
+}
+
+pub async fn until(condition: impl Fn() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+/// parks the processor on a permit held by a slow payment and queues payments until only
+/// `room` are left, so the queue stays as it is until [`resume`]
+pub async fn fill_queue(room: usize) {
+    assert_eq!(patch_max_in_flight(1).await, StatusCode::OK);
+    DEFAULT.set_delay(Duration::from_secs(1));
+    sender().send_async(payment()).await.unwrap();
+    until(|| DEFAULT.received().len() == 1).await;
+    // taken off the queue, then stuck waiting for the only permit
+    sender().send_async(payment()).await.unwrap();
+    until(|| sender().is_empty()).await;
+
+    while sender().len() < QUEUE_CAPACITY - room {
+        sender().try_send(payment()).unwrap();
+    }
+}
+
+pub async fn resume() {
+    DEFAULT.set_delay(Duration::ZERO);
+    assert_eq!(patch_max_in_flight(16).await, StatusCode::OK);
+}
@@ -0,0 +1,279 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::json;
+use sqlx::types::{Decimal, JsonValue};
+
+use crate::{
+    repository::{BucketSize, SummaryBucket},
+    summary::amount,
+};
+
+/// summary broken down into buckets, dense from the first to the last bucket with payments,
+/// every point holds one entry per processor in `processor_names` order
+pub struct TimeSeries {
+    pub processor_names: Vec<String>,
+    pub points: Vec<Point>,
+}
+
+pub struct Point {
+    pub t: DateTime<Utc>,
+    pub stats: Vec<Stats>,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Stats {
+    pub total_amount: Decimal,
+    pub total_requests: i64,
+    pub smoothed: Option<Smoothed>,
+    pub running: Option<Running>,
+    /// change in requests per minute around this bucket
+    pub momentum: Option<f64>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Smoothed {
+    pub total_amount: Decimal,
+    pub total_requests: f64,
+}
+
+/// totals from the first bucket up to and including this one
+#[derive(Clone, Copy)]
+pub struct Running {
+    pub total_amount: Decimal,
+    pub total_requests: i64,
+}
+
+impl TimeSeries {
+    /// points [`TimeSeries::new`] would fill in, first to last bucket with payments inclusive
+    pub fn dense_len(bucket_size: BucketSize, buckets: &[SummaryBucket]) -> usize {
+        match (buckets.first(), buckets.last()) {
+            (Some(first), Some(last)) => {
+                ((last.t - first.t).num_seconds() as f64 / bucket_size.seconds()) as usize + 1
+            }
+            _ => 0,
+        }
+    }
+
+    /// `buckets` must be ordered by bucket, as returned by the repository
+    pub fn new(
+        processor_names: &[String],
+        bucket_size: BucketSize,
+        buckets: &[SummaryBucket],
+    ) -> Self {
+        let mut points: Vec<Point> = Vec::new();
+        let step = TimeDelta::seconds(bucket_size.seconds() as i64);
+
+        for bucket in buckets {
+            while points.last().is_none_or(|last| last.t < bucket.t) {
+                let t = points.last().map_or(bucket.t, |last| last.t + step);
+                points.push(Point {
+                    t,
+                    stats: vec![Stats::default(); processor_names.len()],
+                });
+            }
+
+            if let Some(i) = processor_names
+                .iter()
+                .position(|name| *name == bucket.processed_by)
+            {
+                let stats = &mut points.last_mut().unwrap().stats[i];
+                stats.total_amount = bucket.total_amount;
+                stats.total_requests = bucket.total_requests;
+            }
+        }
+
+        TimeSeries {
+            processor_names: processor_names.to_vec(),
+            points,
+        }
+    }
+
+    /// trailing average over the last `periods` points, shorter at the start of the series
+    pub fn apply_moving_average(&mut self, periods: usize) {
+        let smoothed: Vec<Vec<Smoothed>> = (0..self.points.len())
+            .map(|i| {
+                let window = &self.points[(i + 1).saturating_sub(periods)..=i];
+                let len = window.len();
+
+                (0..self.processor_names.len())
+                    .map(|p| {
+                        let total_amount: Decimal =
+                            window.iter().map(|point| point.stats[p].total_amount).sum();
+                        let total_requests: i64 = window
+                            .iter()
+                            .map(|point| point.stats[p].total_requests)
+                            .sum();
+
+                        Smoothed {
+                            total_amount: (total_amount / Decimal::from(len)).round_dp(2),
+                            total_requests: total_requests as f64 / len as f64,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for (point, smoothed) in self.points.iter_mut().zip(smoothed) {
+            for (stats, smoothed) in point.stats.iter_mut().zip(smoothed) {
+                stats.smoothed = Some(smoothed);
+            }
+        }
+    }
+
+    pub fn apply_running_total(&mut self) {
+        for p in 0..self.processor_names.len() {
+            let running = self.points.iter().scan(
+                (Decimal::ZERO, 0),
+                |(total_amount, total_requests), point| {
+                    *total_amount += point.stats[p].total_amount;
+                    *total_requests += point.stats[p].total_requests;
+                    Some(Running {
+                        total_amount: *total_amount,
+                        total_requests: *total_requests,
+                    })
+                },
+            );
+            let running: Vec<Running> = running.collect();
+
+            for (point, running) in self.points.iter_mut().zip(running) {
+                point.stats[p].running = Some(running);
+            }
+        }
+    }
+
+    /// central difference of requests per bucket, one sided at both ends of the series,
+    /// positive while the load is picking up
+    pub fn apply_momentum(&mut self, bucket_size: BucketSize) {
+        let minutes = bucket_size.seconds() / 60.0;
+        let last = self.points.len().saturating_sub(1);
+
+        for p in 0..self.processor_names.len() {
+            let requests: Vec<f64> = self
+                .points
+                .iter()
+                .map(|point| point.stats[p].total_requests as f64)
+                .collect();
+
+            for (i, point) in self.points.iter_mut().enumerate() {
+                let before = i.saturating_sub(1);
+                let after = (i + 1).min(last);
+                let momentum = if after > before {
+                    (requests[after] - requests[before]) / ((after - before) as f64 * minutes)
+                } else {
+                    0.0
+                };
+                point.stats[p].momentum = Some(momentum);
+            }
+        }
+    }
+
+    /// least squares line through each processor's requests per bucket, extended `horizon`
+    /// past the last bucket, with one projected point per bucket
+    pub fn forecast(&self, bucket_size: BucketSize, horizon: TimeDelta) -> JsonValue {
+        let step = TimeDelta::seconds(bucket_size.seconds() as i64);
+        let Some(last) = self.points.last() else {
+            return json!({});
+        };
+        let steps = (horizon.num_seconds() as f64 / bucket_size.seconds())
+            .ceil()
+            .max(1.0) as usize;
+
+        let mut forecast = json!({});
+        for (p, name) in self.processor_names.iter().enumerate() {
+            let requests: Vec<f64> = self
+                .points
+                .iter()
+                .map(|point| point.stats[p].total_requests as f64)
+                .collect();
+            let (slope, intercept) = linear_fit(&requests);
+
+            forecast[name] = (1..=steps)
+                .map(|k| {
+                    let x = (requests.len() - 1 + k) as f64;
+                    let projected = (slope * x + intercept).max(0.0).round() as i64;
+                    json!({ "t": last.t + step * k as i32, "projectedRequests": projected })
+                })
+                .collect();
+        }
+        forecast
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        self.points
+            .iter()
+            .map(|point| {
+                let mut entry = json!({ "t": point.t });
+                for (name, stats) in self.processor_names.iter().zip(&point.stats) {
+                    entry[name] = stats.to_json();
+                }
+                entry
+            })
+            .collect()
+    }
+
+    /// processors as the outer axis, each with its own list of buckets
+    pub fn to_pivoted_json(&self) -> JsonValue {
+        let mut pivoted = json!({});
+        for (p, name) in self.processor_names.iter().enumerate() {
+            pivoted[name] = self
+                .points
+                .iter()
+                .map(|point| {
+                    let mut entry = point.stats[p].to_json();
+                    entry["t"] = json!(point.t);
+                    entry
+                })
+                .collect();
+        }
+        pivoted
+    }
+}
+
+impl Stats {
+    fn to_json(self) -> JsonValue {
+        let mut stats = json!({
+            "totalAmount": amount(self.total_amount),
+            "totalRequests": self.total_requests,
+        });
+
+        if let Some(running) = self.running {
+            stats["runningTotalAmount"] = amount(running.total_amount);
+            stats["runningTotalRequests"] = json!(running.total_requests);
+        }
+
+        if let Some(momentum) = self.momentum {
+            stats["momentumPerMinute"] = json!(momentum);
+        }
+
+        if let Some(smoothed) = self.smoothed {
+            stats["smoothed"] = json!({
+                "totalAmount": amount(smoothed.total_amount),
+                "totalRequests": smoothed.total_requests,
+            });
+        }
+
+        stats
+    }
+}
+
+/// `(slope, intercept)` of `y = slope * x + intercept` over `x = 0, 1, 2, ...`, flat through the
+/// only point there is when there is just one
+fn linear_fit(ys: &[f64]) -> (f64, f64) {
+    let n = ys.len() as f64;
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = ys.iter().sum::<f64>() / n;
+
+    let (covariance, variance) =
+        ys.iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(covariance, variance), (x, y)| {
+                let dx = x as f64 - mean_x;
+                (covariance + dx * (y - mean_y), variance + dx * dx)
+            });
+
+    let slope = if variance > 0.0 {
+        covariance / variance
+    } else {
+        0.0
+    };
+    (slope, mean_y - slope * mean_x)
+}